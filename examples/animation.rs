@@ -2,6 +2,7 @@
 use rayon::prelude::*;
 use std::f64::consts::PI;
 use std::num::NonZeroU32;
+use std::time::Duration;
 use web_time::Instant;
 use winit::event::{Event, KeyEvent, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
@@ -27,7 +28,7 @@ fn main() {
     .with_event_handler(move |state, event, elwt| {
         let (window, surface, old_size, frames) = state;
 
-        elwt.set_control_flow(ControlFlow::Poll);
+        elwt.set_control_flow(ControlFlow::Wait);
 
         match event {
             Event::WindowEvent {
@@ -50,11 +51,18 @@ fn main() {
                     surface.resize(width, height).unwrap();
                     let mut buffer = surface.buffer_mut().unwrap();
                     buffer.copy_from_slice(frame);
-                    buffer.present().unwrap();
+                    buffer.present_and_request_frame().unwrap();
                 }
             }
             Event::AboutToWait => {
-                window.request_redraw();
+                // Only draw once the display is ready for the next frame, instead of as fast as
+                // possible. winit can't wait on `Surface::readiness_fd`, so check again in a few
+                // milliseconds, which is still well within a frame at common refresh rates.
+                if surface.frame_ready().unwrap() {
+                    window.request_redraw();
+                } else {
+                    elwt.set_control_flow(ControlFlow::wait_duration(Duration::from_millis(4)));
+                }
             }
             Event::WindowEvent {
                 event:
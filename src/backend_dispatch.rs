@@ -1,11 +1,17 @@
 //! Implements `buffer_interface::*` traits for enums dispatching to backends
 
-use crate::{backend_interface::*, backends, InitError, Rect, SoftBufferError};
+use crate::{
+    backend_interface::*, backends, AlphaMode, Backend, Capabilities, FrameTiming, InitError,
+    PixelFormat, PresentMode, Rect, SoftBufferError,
+};
 
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
+#[cfg(unix)]
+use std::os::fd::BorrowedFd;
 #[cfg(any(wayland_platform, x11_platform, kms_platform))]
 use std::sync::Arc;
+use std::time::Duration;
 
 /// A macro for creating the enum used to statically dispatch to the platform-specific implementation.
 macro_rules! make_dispatch {
@@ -33,21 +39,31 @@ macro_rules! make_dispatch {
                     )*
                 }
             }
-        }
 
-        impl<D: HasDisplayHandle> ContextInterface<D> for ContextDispatch<D> {
-            fn new(mut display: D) -> Result<Self, InitError<D>>
-            where
-                D: Sized,
-            {
+            pub fn backend(&self) -> Backend {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(_) => Backend::$name,
+                    )*
+                }
+            }
+
+            /// Try the backends that `allowed` returns `true` for, in order.
+            pub fn new_filtered(
+                mut display: D,
+                allowed: impl Fn(Backend) -> bool,
+            ) -> Result<Self, InitError<D>> {
                 $(
                     $(#[$attr])*
-                    match <$context_inner as ContextInterface<D>>::new(display) {
-                        Ok(x) => {
-                            return Ok(Self::$name(x));
+                    if allowed(Backend::$name) {
+                        match <$context_inner as ContextInterface<D>>::new(display) {
+                            Ok(x) => {
+                                return Ok(Self::$name(x));
+                            }
+                            Err(InitError::Unsupported(d)) => display = d,
+                            Err(InitError::Failure(f)) => return Err(InitError::Failure(f)),
                         }
-                        Err(InitError::Unsupported(d)) => display = d,
-                        Err(InitError::Failure(f)) => return Err(InitError::Failure(f)),
                     }
                 )*
 
@@ -55,6 +71,35 @@ macro_rules! make_dispatch {
             }
         }
 
+        impl<D: HasDisplayHandle> ContextInterface<D> for ContextDispatch<D> {
+            fn new(display: D) -> Result<Self, InitError<D>>
+            where
+                D: Sized,
+            {
+                // Headless contexts are never picked automatically.
+                Self::new_filtered(display, |backend| backend != Backend::Headless)
+            }
+
+            #[cfg(unix)]
+            fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.readiness_fd(),
+                    )*
+                }
+            }
+
+            fn dispatch_pending(&self) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.dispatch_pending(),
+                    )*
+                }
+            }
+        }
+
         #[allow(clippy::large_enum_variant)] // it's boxed anyways
         pub(crate) enum SurfaceDispatch<$dgen, $wgen> {
             $(
@@ -114,6 +159,106 @@ macro_rules! make_dispatch {
                     )*
                 }
             }
+
+            fn capabilities(&self) -> Capabilities {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.capabilities(),
+                    )*
+                }
+            }
+
+            fn supported_formats(&self) -> &[PixelFormat] {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.supported_formats(),
+                    )*
+                }
+            }
+
+            fn set_buffer_count(&mut self, count: NonZeroUsize) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.set_buffer_count(count),
+                    )*
+                }
+            }
+
+            fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.set_present_mode(mode),
+                    )*
+                }
+            }
+
+            fn wait_for_buffer(&mut self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.wait_for_buffer(timeout),
+                    )*
+                }
+            }
+
+            #[cfg(unix)]
+            fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.readiness_fd(),
+                    )*
+                }
+            }
+
+            fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.request_frame(),
+                    )*
+                }
+            }
+
+            fn frame_ready(&mut self) -> Result<bool, SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.frame_ready(),
+                    )*
+                }
+            }
+
+            fn last_frame_timing(&self) -> Option<FrameTiming> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.last_frame_timing(),
+                    )*
+                }
+            }
+
+            fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.set_format(format),
+                    )*
+                }
+            }
+
+            fn set_alpha_mode(&mut self, mode: AlphaMode) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.set_alpha_mode(mode),
+                    )*
+                }
+            }
         }
 
         pub(crate) enum BufferDispatch<'a, $dgen, $wgen> {
@@ -153,6 +298,33 @@ macro_rules! make_dispatch {
                 }
             }
 
+            fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.request_frame(),
+                    )*
+                }
+            }
+
+            fn stride(&self) -> Option<usize> {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.stride(),
+                    )*
+                }
+            }
+
+            fn copy_from_front(&mut self, rects: &[Rect]) -> bool {
+                match self {
+                    $(
+                        $(#[$attr])*
+                        Self::$name(inner) => inner.copy_from_front(rects),
+                    )*
+                }
+            }
+
             fn present(self) -> Result<(), SoftBufferError> {
                 match self {
                     $(
@@ -187,9 +359,44 @@ make_dispatch! {
     #[cfg(target_os = "windows")]
     Win32(D, backends::win32::Win32Impl<D, W>, backends::win32::BufferImpl<'a, D, W>),
     #[cfg(target_os = "macos")]
-    CG(D, backends::cg::CGImpl<D, W>, backends::cg::BufferImpl<'a, D, W>),
+    CoreGraphics(D, backends::cg::CGImpl<D, W>, backends::cg::BufferImpl<'a, D, W>),
     #[cfg(target_arch = "wasm32")]
     Web(backends::web::WebDisplayImpl<D>, backends::web::WebImpl<D, W>, backends::web::BufferImpl<'a, D, W>),
     #[cfg(target_os = "redox")]
     Orbital(D, backends::orbital::OrbitalImpl<D, W>, backends::orbital::BufferImpl<'a, D, W>),
+    Headless(backends::headless::HeadlessDisplayImpl<D>, backends::headless::HeadlessImpl<D, W>, backends::headless::BufferImpl<'a, D, W>),
+}
+
+#[cfg(all(feature = "calloop", free_unix))]
+impl<D: HasDisplayHandle> ContextDispatch<D> {
+    /// Share the connection of this context, if the backend has one.
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        match self {
+            #[cfg(x11_platform)]
+            Self::X11(inner) => Some(Self::X11(inner.clone())),
+            #[cfg(wayland_platform)]
+            Self::Wayland(inner) => Some(Self::Wayland(inner.clone())),
+            #[cfg(kms_platform)]
+            Self::Kms(inner) => Some(Self::Kms(inner.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(feature = "async", free_unix))]
+impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferDispatch<'a, D, W> {
+    /// Something to wait on after presenting this buffer, until the next buffer is available.
+    ///
+    /// `None` if presenting doesn't need to be waited for.
+    pub(crate) fn frame_waiter(
+        &self,
+    ) -> Result<Option<Box<dyn crate::reactor::FrameWait + 'a>>, SoftBufferError> {
+        Ok(match self {
+            #[cfg(wayland_platform)]
+            Self::Wayland(inner) => Some(Box::new(inner.frame_waiter())),
+            #[cfg(kms_platform)]
+            Self::Kms(inner) => Some(Box::new(inner.frame_waiter()?)),
+            _ => None,
+        })
+    }
 }
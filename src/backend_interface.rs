@@ -1,15 +1,30 @@
 //! Interface implemented by backends
 
-use crate::{InitError, Rect, SoftBufferError};
+use crate::{
+    AlphaMode, Capabilities, FrameTiming, InitError, PixelFormat, PresentMode, Rect,
+    SoftBufferError,
+};
 
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
+#[cfg(unix)]
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
 
 pub(crate) trait ContextInterface<D: HasDisplayHandle + ?Sized> {
     fn new(display: D) -> Result<Self, InitError<D>>
     where
         D: Sized,
         Self: Sized;
+    /// A file descriptor that becomes readable when the display has events for softbuffer.
+    #[cfg(unix)]
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+    /// Handle the events that arrived without blocking.
+    fn dispatch_pending(&self) -> Result<(), SoftBufferError> {
+        Ok(())
+    }
 }
 
 pub(crate) trait SurfaceInterface<D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> {
@@ -32,12 +47,95 @@ pub(crate) trait SurfaceInterface<D: HasDisplayHandle + ?Sized, W: HasWindowHand
     fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
         Err(SoftBufferError::Unimplemented)
     }
+    /// What the backend supports, except for the `formats`, which the core fills in.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new()
+    }
+    /// The formats that can be presented without any conversion, best first.
+    ///
+    /// The surface must start out presenting in the first one.
+    fn supported_formats(&self) -> &[PixelFormat] {
+        &[PixelFormat::Xrgb8888]
+    }
+    /// Switch the buffers to one of the `supported_formats()`.
+    fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        if format == PixelFormat::Xrgb8888 {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+    /// Change how the `X` bits of the pixels are treated.
+    fn set_alpha_mode(&mut self, mode: AlphaMode) -> Result<(), SoftBufferError> {
+        if mode == AlphaMode::Opaque {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+    /// Change the number of buffers, before the first one is used.
+    fn set_buffer_count(&mut self, count: NonZeroUsize) -> Result<(), SoftBufferError> {
+        if count.get() == 1 {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+    /// Change when presented frames are shown.
+    fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), SoftBufferError> {
+        if mode == PresentMode::Vsync {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+    /// Wait until `buffer_mut` won't block, for at most `timeout` if it is set.
+    ///
+    /// Returns `false` if the timeout ran out first.
+    fn wait_for_buffer(&mut self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+        let _ = timeout;
+        Ok(true)
+    }
+    /// A file descriptor that becomes readable when the display has events for this surface, and
+    /// when `wait_for_buffer` may have stopped blocking.
+    #[cfg(unix)]
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+    /// Ask for `frame_ready` to wait for the display after the next present.
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        Ok(())
+    }
+    /// Check if the display is ready for the frame requested with `request_frame`, without
+    /// blocking.
+    fn frame_ready(&mut self) -> Result<bool, SoftBufferError> {
+        Ok(true)
+    }
+    /// When the last presented frame was shown, if the display reported it.
+    fn last_frame_timing(&self) -> Option<FrameTiming> {
+        None
+    }
 }
 
 pub(crate) trait BufferInterface {
     fn pixels(&self) -> &[u32];
     fn pixels_mut(&mut self) -> &mut [u32];
     fn age(&self) -> u8;
+    /// The number of pixels from the start of one row to the next, if the rows are padded.
+    fn stride(&self) -> Option<usize> {
+        None
+    }
+    /// Copy `rects` of the last presented frame into this buffer.
+    ///
+    /// Returns `false` if the backend doesn't have the last frame anymore.
+    fn copy_from_front(&mut self, rects: &[Rect]) -> bool {
+        let _ = rects;
+        false
+    }
+    /// Like `SurfaceInterface::request_frame`, for when this buffer is presented.
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        Ok(())
+    }
     fn present_with_damage(self, damage: &[Rect]) -> Result<(), SoftBufferError>;
     fn present(self) -> Result<(), SoftBufferError>;
 }
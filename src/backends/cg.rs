@@ -1,6 +1,6 @@
 use crate::backend_interface::*;
-use crate::error::InitError;
-use crate::{Rect, SoftBufferError};
+use crate::error::{InitError, SwResultExt};
+use crate::{Capabilities, ErrorKind, Rect, SoftBufferError};
 use core_graphics::base::{
     kCGBitmapByteOrder32Little, kCGImageAlphaFirst, kCGRenderingIntentDefault,
 };
@@ -55,10 +55,10 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for CGImpl<
         };
 
         // `NSView` can only be accessed from the main thread.
-        let mtm = MainThreadMarker::new().ok_or(SoftBufferError::PlatformError(
-            Some("can only access AppKit / macOS handles from the main thread".to_string()),
-            None,
-        ))?;
+        let mtm = MainThreadMarker::new().swbuf_err_kind(
+            ErrorKind::InvalidInput,
+            "can only access AppKit / macOS handles from the main thread",
+        )?;
         let view = handle.ns_view.as_ptr();
         // SAFETY: The pointer came from `WindowHandle`, which ensures that
         // the `AppKitWindowHandle` contains a valid pointer to an `NSView`.
@@ -76,10 +76,9 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for CGImpl<
             ))
         };
 
-        let window = view.window().ok_or(SoftBufferError::PlatformError(
-            Some("view must be inside a window".to_string()),
-            None,
-        ))?;
+        let window = view
+            .window()
+            .swbuf_err_kind(ErrorKind::InvalidInput, "view must be inside a window")?;
 
         unsafe { view.addSubview(&subview) };
         let color_space = CGColorSpace::create_device_rgb();
@@ -98,15 +97,21 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for CGImpl<
         &self.window_handle
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // Every frame is copied into a new image.
+        Capabilities {
+            max_buffer_age: 0,
+            ..Capabilities::new()
+        }
+    }
+
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
         self.size = Some((width, height));
         Ok(())
     }
 
     fn buffer_mut(&mut self) -> Result<BufferImpl<'_, D, W>, SoftBufferError> {
-        let (width, height) = self
-            .size
-            .expect("Must set size of surface before calling `buffer_mut()`");
+        let (width, height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
 
         Ok(BufferImpl {
             buffer: vec![0; width.get() as usize * height.get() as usize],
@@ -137,7 +142,7 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferInterface for BufferImpl
 
     fn present(self) -> Result<(), SoftBufferError> {
         let data_provider = CGDataProvider::from_buffer(Arc::new(Buffer(self.buffer)));
-        let (width, height) = self.imp.size.unwrap();
+        let (width, height) = self.imp.size.ok_or(SoftBufferError::SizeNotSet)?;
         let image = CGImage::new(
             width.get() as usize,
             height.get() as usize,
@@ -152,10 +157,10 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferInterface for BufferImpl
         );
 
         // TODO: Use run_on_main() instead.
-        let mtm = MainThreadMarker::new().ok_or(SoftBufferError::PlatformError(
-            Some("can only access AppKit / macOS handles from the main thread".to_string()),
-            None,
-        ))?;
+        let mtm = MainThreadMarker::new().swbuf_err_kind(
+            ErrorKind::InvalidInput,
+            "can only access AppKit / macOS handles from the main thread",
+        )?;
 
         // The CALayer has a default action associated with a change in the layer contents, causing
         // a quarter second fade transition to happen every time a new buffer is applied. This can
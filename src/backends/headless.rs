@@ -0,0 +1,388 @@
+//! A backend that renders into memory instead of a window.
+//!
+//! This needs no window system at all, which makes it useful for tests and servers. It double
+//! buffers like the Wayland and KMS backends, and records every frame that is presented.
+
+use crate::backend_interface::*;
+use crate::error::InitError;
+use crate::util;
+use crate::{AlphaMode, Capabilities, PixelFormat, PresentMode, Rect, SoftBufferError};
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use std::marker::PhantomData;
+use std::num::{NonZeroU32, NonZeroUsize};
+
+/// The context of the headless backend.
+///
+/// There is nothing to connect to, so this holds no state.
+pub struct HeadlessDisplayImpl<D> {
+    _display: PhantomData<D>,
+}
+
+impl<D> HeadlessDisplayImpl<D> {
+    pub(crate) fn new() -> Self {
+        Self {
+            _display: PhantomData,
+        }
+    }
+}
+
+impl<D: HasDisplayHandle> ContextInterface<D> for HeadlessDisplayImpl<D> {
+    fn new(_display: D) -> Result<Self, InitError<D>> {
+        // There is nothing to connect to, so the display isn't needed.
+        Ok(Self::new())
+    }
+}
+
+/// A frame that was presented to a headless surface.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct PresentedFrame {
+    /// The width of the surface when the frame was presented.
+    pub width: NonZeroU32,
+
+    /// The height of the surface when the frame was presented.
+    pub height: NonZeroU32,
+
+    /// The contents of the buffer, in `format`.
+    pub pixels: Vec<u32>,
+
+    /// The format of `pixels`.
+    pub format: PixelFormat,
+
+    /// The damage the frame was presented with.
+    pub damage: Vec<Rect>,
+}
+
+/// One of the two in-memory buffers.
+struct HeadlessBuffer {
+    /// The pixel data.
+    pixels: Vec<u32>,
+
+    /// The age of this buffer.
+    age: u8,
+}
+
+impl HeadlessBuffer {
+    fn new(len: usize) -> Self {
+        Self {
+            pixels: vec![0; len],
+            age: 0,
+        }
+    }
+}
+
+pub struct HeadlessImpl<D, W> {
+    /// The buffer that was presented last.
+    front: HeadlessBuffer,
+
+    /// The buffer that is handed out next.
+    back: HeadlessBuffer,
+
+    /// The current surface width/height.
+    size: Option<(NonZeroU32, NonZeroU32)>,
+
+    /// The format of the buffers.
+    format: PixelFormat,
+
+    /// Every frame that was presented, oldest first.
+    frames: Vec<PresentedFrame>,
+
+    /// The window handle, which is only kept around.
+    window_handle: W,
+
+    _display: PhantomData<D>,
+}
+
+impl<D, W> HeadlessImpl<D, W> {
+    pub(crate) fn from_window(window: W) -> Self {
+        Self {
+            front: HeadlessBuffer::new(0),
+            back: HeadlessBuffer::new(0),
+            size: None,
+            format: PixelFormat::default(),
+            frames: Vec::new(),
+            window_handle: window,
+            _display: PhantomData,
+        }
+    }
+
+    /// Get the frames presented so far.
+    pub(crate) fn presented_frames(&self) -> &[PresentedFrame] {
+        &self.frames
+    }
+
+    /// Take the frames presented so far, clearing the record.
+    pub(crate) fn take_presented_frames(&mut self) -> Vec<PresentedFrame> {
+        std::mem::take(&mut self.frames)
+    }
+
+    fn present_with_damage(&mut self, damage: &[Rect]) -> Result<(), SoftBufferError> {
+        let (width, height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
+
+        // Reject the same damage that the other backends can't represent.
+        for rect in damage {
+            if rect.x.checked_add(rect.width.get()).is_none()
+                || rect.y.checked_add(rect.height.get()).is_none()
+            {
+                return Err(SoftBufferError::DamageOutOfRange { rect: *rect });
+            }
+        }
+
+        // Swap front and back buffer
+        std::mem::swap(&mut self.front, &mut self.back);
+
+        self.front.age = 1;
+        if self.back.age != 0 {
+            self.back.age += 1;
+        }
+
+        self.frames.push(PresentedFrame {
+            width,
+            height,
+            pixels: self.front.pixels.clone(),
+            format: self.format,
+            damage: damage.to_vec(),
+        });
+
+        Ok(())
+    }
+}
+
+impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for HeadlessImpl<D, W> {
+    type Context = HeadlessDisplayImpl<D>;
+    type Buffer<'a> = BufferImpl<'a, D, W> where Self: 'a;
+
+    fn new(window: W, _display: &HeadlessDisplayImpl<D>) -> Result<Self, InitError<W>> {
+        Ok(Self::from_window(window))
+    }
+
+    #[inline]
+    fn window(&self) -> &W {
+        &self.window_handle
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            fetch: true,
+            partial_damage: true,
+            max_buffer_age: 2,
+            present_modes: vec![PresentMode::Vsync, PresentMode::Immediate],
+            ..Capabilities::new()
+        }
+    }
+
+    fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
+        if self.size != Some((width, height)) {
+            let len = (width.get() as usize)
+                .checked_mul(height.get() as usize)
+                .ok_or(SoftBufferError::SizeOutOfRange { width, height })?;
+
+            // Like a real backend, a resize throws away both buffers.
+            self.front = HeadlessBuffer::new(len);
+            self.back = HeadlessBuffer::new(len);
+            self.size = Some((width, height));
+        }
+
+        Ok(())
+    }
+
+    fn buffer_mut(&mut self) -> Result<BufferImpl<'_, D, W>, SoftBufferError> {
+        if self.size.is_none() {
+            return Err(SoftBufferError::SizeNotSet);
+        }
+
+        Ok(BufferImpl(self))
+    }
+
+    fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
+        if self.size.is_none() {
+            return Err(SoftBufferError::SizeNotSet);
+        }
+
+        Ok(self.front.pixels.clone())
+    }
+
+    fn supported_formats(&self) -> &[PixelFormat] {
+        // Memory has no opinion on the format.
+        PixelFormat::ALL
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        self.format = format;
+        Ok(())
+    }
+
+    fn set_alpha_mode(&mut self, _mode: AlphaMode) -> Result<(), SoftBufferError> {
+        // The pixels are recorded as they are, alpha or not.
+        Ok(())
+    }
+
+    fn set_buffer_count(&mut self, count: NonZeroUsize) -> Result<(), SoftBufferError> {
+        if count.get() == 2 {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+
+    fn set_present_mode(&mut self, _mode: PresentMode) -> Result<(), SoftBufferError> {
+        // There is no display to synchronize with.
+        Ok(())
+    }
+}
+
+pub struct BufferImpl<'a, D, W>(&'a mut HeadlessImpl<D, W>);
+
+impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferInterface for BufferImpl<'a, D, W> {
+    #[inline]
+    fn pixels(&self) -> &[u32] {
+        &self.0.back.pixels
+    }
+
+    #[inline]
+    fn pixels_mut(&mut self) -> &mut [u32] {
+        &mut self.0.back.pixels
+    }
+
+    fn age(&self) -> u8 {
+        self.0.back.age
+    }
+
+    fn copy_from_front(&mut self, rects: &[Rect]) -> bool {
+        let Some((width, _)) = self.0.size else {
+            return false;
+        };
+        let HeadlessImpl { front, back, .. } = &mut *self.0;
+        if front.age == 0 {
+            return false;
+        }
+
+        util::copy_rects(&front.pixels, &mut back.pixels, width.get() as usize, rects);
+        true
+    }
+
+    fn present_with_damage(self, damage: &[Rect]) -> Result<(), SoftBufferError> {
+        self.0.present_with_damage(damage)
+    }
+
+    fn present(self) -> Result<(), SoftBufferError> {
+        let (width, height) = self.0.size.ok_or(SoftBufferError::SizeNotSet)?;
+        self.0.present_with_damage(&[Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Context, NoWindowHandle, Rect, Surface};
+    use std::num::NonZeroU32;
+
+    fn size(width: u32, height: u32) -> (NonZeroU32, NonZeroU32) {
+        (
+            NonZeroU32::new(width).unwrap(),
+            NonZeroU32::new(height).unwrap(),
+        )
+    }
+
+    #[test]
+    fn ages_follow_double_buffering() {
+        let (width, height) = size(4, 3);
+        let mut surface = Surface::new_headless(width, height).unwrap();
+
+        let mut ages = Vec::new();
+        for _ in 0..4 {
+            let buffer = surface.buffer_mut().unwrap();
+            ages.push(buffer.age());
+            buffer.present().unwrap();
+        }
+        assert_eq!(ages, [0, 0, 2, 2]);
+
+        surface.resize(width, NonZeroU32::new(5).unwrap()).unwrap();
+        assert_eq!(surface.buffer_mut().unwrap().age(), 0);
+    }
+
+    #[test]
+    fn frames_are_recorded() {
+        let (width, height) = size(2, 2);
+        let mut surface = Surface::new_headless(width, height).unwrap();
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer.copy_from_slice(&[1, 2, 3, 4]);
+        buffer.present().unwrap();
+
+        let damage = Rect {
+            x: 1,
+            y: 0,
+            width: NonZeroU32::new(1).unwrap(),
+            height: NonZeroU32::new(2).unwrap(),
+        };
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer.copy_from_slice(&[5, 6, 7, 8]);
+        buffer.present_with_damage(&[damage]).unwrap();
+
+        let frames = surface.take_presented_frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].pixels, [1, 2, 3, 4]);
+        assert_eq!(frames[0].damage.len(), 1);
+        assert_eq!(frames[1].pixels, [5, 6, 7, 8]);
+        assert_eq!(frames[1].damage[0].x, 1);
+        assert!(surface.presented_frames().is_empty());
+
+        assert_eq!(surface.fetch().unwrap(), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn surface_from_headless_context() {
+        let context = Context::headless();
+        let mut surface = Surface::new(&context, NoWindowHandle(())).unwrap();
+        let (width, height) = size(3, 1);
+        surface.resize(width, height).unwrap();
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer.fill(0x00ff0000);
+        buffer.present().unwrap();
+
+        assert_eq!(surface.presented_frames()[0].pixels, [0x00ff0000; 3]);
+    }
+
+    #[test]
+    fn headless_has_nothing_to_wait_on() {
+        let context = Context::headless();
+        let (width, height) = size(2, 2);
+        let surface = Surface::new_headless(width, height).unwrap();
+
+        #[cfg(unix)]
+        {
+            assert!(context.readiness_fd().is_none());
+            assert!(surface.readiness_fd().is_none());
+        }
+        context.dispatch_pending().unwrap();
+        #[cfg(feature = "calloop")]
+        assert!(matches!(
+            context.event_source(),
+            Err(crate::SoftBufferError::Unimplemented)
+        ));
+    }
+
+    #[test]
+    fn headless_frames_are_ready_right_away() {
+        let (width, height) = size(2, 2);
+        let mut surface = Surface::new_headless(width, height).unwrap();
+        assert!(surface.frame_ready().unwrap());
+
+        surface.request_frame().unwrap();
+        surface.buffer_mut().unwrap().present().unwrap();
+        assert!(surface.frame_ready().unwrap());
+
+        let buffer = surface.buffer_mut().unwrap();
+        buffer.present_and_request_frame().unwrap();
+        assert!(surface.frame_ready().unwrap());
+        assert_eq!(surface.presented_frames().len(), 2);
+        // Nothing reports when frames are shown.
+        assert_eq!(surface.last_frame_timing(), None);
+    }
+}
@@ -2,33 +2,83 @@
 //!
 //! This strategy uses dumb buffers for rendering.
 
-use drm::buffer::{Buffer, DrmFourcc};
+use drm::buffer::{Buffer, DrmFourcc, DrmModifier, Handle, PlanarBuffer};
 use drm::control::dumbbuffer::{DumbBuffer, DumbMapping};
 use drm::control::{
-    connector, crtc, framebuffer, plane, ClipRect, Device as CtrlDevice, PageFlipFlags,
+    connector, crtc, framebuffer, plane, ClipRect, Device as CtrlDevice, Event, FbCmd2Flags,
+    PageFlipFlags,
 };
-use drm::Device;
+use drm::{Device, DriverCapability};
 
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle};
 
 use std::collections::HashSet;
 use std::marker::PhantomData;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::os::unix::io::{AsFd, BorrowedFd};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::backend_interface::*;
 use crate::error::{InitError, SoftBufferError, SwResultExt};
+use crate::{util, Capabilities, PixelFormat, PresentMode};
 
 #[derive(Debug)]
 pub(crate) struct KmsDisplayImpl<D: ?Sized> {
     /// The underlying raw device file descriptor.
     fd: BorrowedFd<'static>,
 
+    /// The CRTCs that flipped pages since they were last checked.
+    flipped: Mutex<HashSet<crtc::Handle>>,
+
     /// Holds a reference to the display.
     _display: D,
 }
 
+impl<D: ?Sized> KmsDisplayImpl<D> {
+    /// Read the events of the DRM device, without blocking.
+    fn read_events(&self) -> Result<(), SoftBufferError>
+    where
+        D: Sized,
+    {
+        if !super::poll_readable(self.fd, Some(Duration::ZERO))
+            .swbuf_err("failed to poll the DRM device")?
+        {
+            return Ok(());
+        }
+
+        let events = self
+            .receive_events()
+            .swbuf_err("failed to receive DRM events")?;
+        let mut flipped = self.flipped.lock().unwrap_or_else(|x| x.into_inner());
+        for event in events {
+            if let Event::PageFlip(flip) = event {
+                flipped.insert(flip.crtc);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if `crtc` flipped pages since the last check.
+    fn take_page_flip(&self, crtc: crtc::Handle) -> bool {
+        self.flipped
+            .lock()
+            .unwrap_or_else(|x| x.into_inner())
+            .remove(&crtc)
+    }
+
+    /// Forget the page flips of `crtc` that were never waited for, including the ones that are
+    /// still unread, so they aren't mistaken for the next one.
+    fn forget_page_flips(&self, crtc: crtc::Handle) -> Result<(), SoftBufferError>
+    where
+        D: Sized,
+    {
+        self.read_events()?;
+        self.take_page_flip(crtc);
+        Ok(())
+    }
+}
+
 impl<D: ?Sized> AsFd for KmsDisplayImpl<D> {
     fn as_fd(&self) -> BorrowedFd<'_> {
         self.fd
@@ -38,7 +88,7 @@ impl<D: ?Sized> AsFd for KmsDisplayImpl<D> {
 impl<D: ?Sized> Device for KmsDisplayImpl<D> {}
 impl<D: ?Sized> CtrlDevice for KmsDisplayImpl<D> {}
 
-impl<D: HasDisplayHandle + ?Sized> ContextInterface<D> for Arc<KmsDisplayImpl<D>> {
+impl<D: HasDisplayHandle> ContextInterface<D> for Arc<KmsDisplayImpl<D>> {
     fn new(display: D) -> Result<Self, InitError<D>>
     where
         D: Sized,
@@ -56,9 +106,18 @@ impl<D: HasDisplayHandle + ?Sized> ContextInterface<D> for Arc<KmsDisplayImpl<D>
 
         Ok(Arc::new(KmsDisplayImpl {
             fd,
+            flipped: Mutex::default(),
             _display: display,
         }))
     }
+
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.fd)
+    }
+
+    fn dispatch_pending(&self) -> Result<(), SoftBufferError> {
+        self.read_events()
+    }
 }
 
 /// All the necessary types for the Drm/Kms backend.
@@ -76,10 +135,35 @@ pub(crate) struct KmsImpl<D: ?Sized, W: ?Sized> {
     /// The dumb buffer we're using as a buffer.
     buffer: Option<Buffers>,
 
+    /// The formats the plane supports, best first.
+    formats: Vec<PixelFormat>,
+
+    /// The format of the buffers.
+    format: PixelFormat,
+
+    /// The flags to page flip with.
+    page_flip_flags: PageFlipFlags,
+
+    /// Whether the driver can flip pages without waiting for vertical blank.
+    async_page_flip: bool,
+
+    /// The page flip that `frame_ready` waits for.
+    frame: FrameState,
+
     /// Window handle that we are keeping around.
     window_handle: W,
 }
 
+/// The page flip that `frame_ready` waits for.
+#[derive(Debug, Default)]
+struct FrameState {
+    /// Wait for the page flip of the next present.
+    requested: bool,
+
+    /// A requested page flip hasn't happened yet.
+    pending: bool,
+}
+
 #[derive(Debug)]
 struct Buffers {
     /// The involved set of buffers.
@@ -87,6 +171,10 @@ struct Buffers {
 
     /// Whether to use the first buffer or the second buffer as the front buffer.
     first_is_front: bool,
+
+    /// The pixels handed out for 16-bit formats, which are packed into the dumb buffers when
+    /// they are presented.
+    unpacked: Option<Vec<u32>>,
 }
 
 /// The buffer implementation.
@@ -94,15 +182,27 @@ pub(crate) struct BufferImpl<'a, D: ?Sized, W: ?Sized> {
     /// The mapping of the dump buffer.
     mapping: DumbMapping<'a>,
 
+    /// The pitch of the dumb buffer, in bytes.
+    pitch: u32,
+
+    /// The pixels to pack into `mapping`, for 16-bit formats.
+    unpacked: Option<&'a mut [u32]>,
+
     /// The framebuffer object of the current front buffer.
     front_fb: framebuffer::Handle,
 
     /// The CRTC handle.
     crtc_handle: crtc::Handle,
 
+    /// The flags to page flip with.
+    page_flip_flags: PageFlipFlags,
+
     /// This is used to change the front buffer.
     first_is_front: &'a mut bool,
 
+    /// The page flip that `frame_ready` waits for.
+    frame: &'a mut FrameState,
+
     /// The current size.
     size: (NonZeroU32, NonZeroU32),
 
@@ -115,6 +215,9 @@ pub(crate) struct BufferImpl<'a, D: ?Sized, W: ?Sized> {
     /// Age of the back buffer.
     back_age: &'a mut u8,
 
+    /// The dumb buffer that is on screen, which holds the last presented frame.
+    back_db: &'a mut DumbBuffer,
+
     /// Window reference.
     _window: PhantomData<&'a mut W>,
 }
@@ -132,7 +235,7 @@ struct SharedBuffer {
     age: u8,
 }
 
-impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> for KmsImpl<D, W> {
+impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for KmsImpl<D, W> {
     type Context = Arc<KmsDisplayImpl<D>>;
     type Buffer<'a> = BufferImpl<'a, D, W> where Self: 'a;
 
@@ -191,16 +294,47 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
             .filter(|connector| {
                 connector
                     .current_encoder()
-                    .map_or(false, |encoder| encoders.contains(&encoder))
+                    .is_some_and(|encoder| encoders.contains(&encoder))
             })
             .map(|info| info.handle())
             .collect::<Vec<_>>();
 
+        let mut formats = plane_info
+            .formats()
+            .iter()
+            .filter_map(|&fourcc| match DrmFourcc::try_from(fourcc) {
+                Ok(DrmFourcc::Xrgb8888) => Some(PixelFormat::Xrgb8888),
+                Ok(DrmFourcc::Xbgr8888) => Some(PixelFormat::Xbgr8888),
+                Ok(DrmFourcc::Xrgb2101010) => Some(PixelFormat::Xrgb2101010),
+                Ok(DrmFourcc::Rgb565) => Some(PixelFormat::Rgb565),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Prefer the default format, and assume it works if the plane doesn't list any formats
+        // we know.
+        if let Some(index) = formats.iter().position(|&f| f == PixelFormat::Xrgb8888) {
+            formats[..=index].rotate_right(1);
+        } else if formats.is_empty() {
+            formats.push(PixelFormat::Xrgb8888);
+        }
+        let format = formats[0];
+
+        // Pages are flipped with the legacy ioctl, so the atomic capability doesn't matter.
+        let async_page_flip = display
+            .get_driver_capability(DriverCapability::ASyncPageFlip)
+            .is_ok_and(|value| value != 0);
+
         Ok(Self {
             crtc,
             connectors,
             display: display.clone(),
             buffer: None,
+            formats,
+            format,
+            page_flip_flags: PageFlipFlags::EVENT,
+            async_page_flip,
+            frame: FrameState::default(),
             window_handle: window,
         })
     }
@@ -210,6 +344,10 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
         &self.window_handle
     }
 
+    fn capabilities(&self) -> Capabilities {
+        capabilities(self.async_page_flip)
+    }
+
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
         // Don't resize if we don't have to.
         if let Some(buffer) = &self.buffer {
@@ -219,18 +357,67 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
             }
         }
 
-        // Create a new buffer set.
-        let front_buffer = SharedBuffer::new(&self.display, width, height)?;
-        let back_buffer = SharedBuffer::new(&self.display, width, height)?;
+        self.create_buffers(width, height)
+    }
 
-        self.buffer = Some(Buffers {
-            first_is_front: true,
-            buffers: [front_buffer, back_buffer],
-        });
+    fn supported_formats(&self) -> &[PixelFormat] {
+        &self.formats
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        if !self.formats.contains(&format) {
+            return Err(SoftBufferError::Unimplemented);
+        }
 
+        if format != self.format {
+            self.format = format;
+
+            // Recreate the buffers in the new format.
+            if let Some(buffer) = &self.buffer {
+                let (width, height) = buffer.size();
+                self.create_buffers(width, height)?;
+            }
+        }
         Ok(())
     }
 
+    fn set_buffer_count(&mut self, count: NonZeroUsize) -> Result<(), SoftBufferError> {
+        if count.get() == 2 {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+
+    fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), SoftBufferError> {
+        self.page_flip_flags = match mode {
+            PresentMode::Vsync => PageFlipFlags::EVENT,
+            PresentMode::Immediate if self.async_page_flip => {
+                PageFlipFlags::EVENT | PageFlipFlags::ASYNC
+            }
+            // Flipping would fail, rather than waiting for vertical blank.
+            PresentMode::Immediate => return Err(SoftBufferError::Unimplemented),
+        };
+        Ok(())
+    }
+
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.display.readiness_fd()
+    }
+
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        self.frame.requested = true;
+        Ok(())
+    }
+
+    fn frame_ready(&mut self) -> Result<bool, SoftBufferError> {
+        if self.frame.pending {
+            self.display.read_events()?;
+            self.frame.pending = !self.display.take_page_flip(self.crtc.handle());
+        }
+        Ok(!self.frame.pending)
+    }
+
     /*
     fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
         // TODO: Implement this!
@@ -239,10 +426,7 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
 
     fn buffer_mut(&mut self) -> Result<BufferImpl<'_, D, W>, SoftBufferError> {
         // Map the dumb buffer.
-        let set = self
-            .buffer
-            .as_mut()
-            .expect("Must set size of surface before calling `buffer_mut()`");
+        let set = self.buffer.as_mut().ok_or(SoftBufferError::SizeNotSet)?;
 
         let size = set.size();
 
@@ -254,8 +438,10 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
         };
 
         let front_fb = front_buffer.fb;
+        let pitch = front_buffer.db.pitch();
         let front_age = &mut front_buffer.age;
         let back_age = &mut back_buffer.age;
+        let back_db = &mut back_buffer.db;
 
         let mapping = self
             .display
@@ -264,18 +450,47 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
 
         Ok(BufferImpl {
             mapping,
+            pitch,
+            unpacked: set.unpacked.as_deref_mut(),
             size,
             first_is_front: &mut set.first_is_front,
+            frame: &mut self.frame,
             front_fb,
             crtc_handle: self.crtc.handle(),
+            page_flip_flags: self.page_flip_flags,
             display: &self.display,
             front_age,
             back_age,
+            back_db,
             _window: PhantomData,
         })
     }
 }
 
+impl<D: ?Sized, W: ?Sized> KmsImpl<D, W> {
+    /// Create a new buffer set.
+    fn create_buffers(
+        &mut self,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> Result<(), SoftBufferError> {
+        let front_buffer = SharedBuffer::new(&self.display, width, height, self.format)?;
+        let back_buffer = SharedBuffer::new(&self.display, width, height, self.format)?;
+
+        // Our buffers use 32 bits per pixel, so 16-bit formats need a buffer to pack from.
+        let unpacked = (self.format == PixelFormat::Rgb565)
+            .then(|| vec![0; width.get() as usize * height.get() as usize]);
+
+        self.buffer = Some(Buffers {
+            first_is_front: true,
+            buffers: [front_buffer, back_buffer],
+            unpacked,
+        });
+
+        Ok(())
+    }
+}
+
 impl<D: ?Sized, W: ?Sized> Drop for KmsImpl<D, W> {
     fn drop(&mut self) {
         // Map the CRTC to the information that was there before.
@@ -291,15 +506,59 @@ impl<D: ?Sized, W: ?Sized> Drop for KmsImpl<D, W> {
     }
 }
 
-impl<D: ?Sized, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
+#[cfg(feature = "async")]
+impl<'a, D, W: ?Sized> BufferImpl<'a, D, W> {
+    /// Wait for the page flip scheduled by presenting this buffer.
+    pub(crate) fn frame_waiter(&self) -> Result<FrameWaiter<'a, D>, SoftBufferError> {
+        FrameWaiter::new(self.display, self.crtc_handle)
+    }
+}
+
+/// Waits for a page flip on a CRTC, see [`BufferImpl::frame_waiter`].
+#[cfg(feature = "async")]
+pub(crate) struct FrameWaiter<'a, D: ?Sized> {
+    display: &'a KmsDisplayImpl<D>,
+    crtc: crtc::Handle,
+}
+
+#[cfg(feature = "async")]
+impl<'a, D> FrameWaiter<'a, D> {
+    /// Wait for the next page flip of `crtc`.
+    fn new(display: &'a KmsDisplayImpl<D>, crtc: crtc::Handle) -> Result<Self, SoftBufferError> {
+        // Every earlier page flip must have finished, or presenting would fail, but its event may
+        // not have been read yet.
+        display.forget_page_flips(crtc)?;
+        Ok(Self { display, crtc })
+    }
+}
+
+#[cfg(feature = "async")]
+impl<D> crate::reactor::FrameWait for FrameWaiter<'_, D> {
+    fn fd(&self) -> BorrowedFd<'_> {
+        self.display.as_fd()
+    }
+
+    fn is_done(&mut self) -> Result<bool, SoftBufferError> {
+        self.display.read_events()?;
+        Ok(self.display.take_page_flip(self.crtc))
+    }
+}
+
+impl<D, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
     #[inline]
     fn pixels(&self) -> &[u32] {
-        bytemuck::cast_slice(self.mapping.as_ref())
+        match &self.unpacked {
+            Some(unpacked) => unpacked,
+            None => bytemuck::cast_slice(self.mapping.as_ref()),
+        }
     }
 
     #[inline]
     fn pixels_mut(&mut self) -> &mut [u32] {
-        bytemuck::cast_slice_mut(self.mapping.as_mut())
+        match &mut self.unpacked {
+            Some(unpacked) => unpacked,
+            None => bytemuck::cast_slice_mut(self.mapping.as_mut()),
+        }
     }
 
     #[inline]
@@ -307,8 +566,44 @@ impl<D: ?Sized, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
         *self.front_age
     }
 
+    fn copy_from_front(&mut self, rects: &[crate::Rect]) -> bool {
+        // The unpacked pixels are shared by both buffers, so they always hold the last frame.
+        if self.unpacked.is_some() {
+            return true;
+        }
+        if *self.back_age == 0 {
+            return false;
+        }
+
+        let front = match self.display.map_dumb_buffer(self.back_db) {
+            Ok(front) => front,
+            Err(_) => return false,
+        };
+        util::copy_rects(
+            bytemuck::cast_slice(front.as_ref()),
+            bytemuck::cast_slice_mut(self.mapping.as_mut()),
+            self.pitch as usize / 4,
+            rects,
+        );
+        true
+    }
+
+    #[inline]
+    fn stride(&self) -> Option<usize> {
+        // The unpacked pixels are never padded, but the dumb buffer's pitch may be.
+        match self.unpacked {
+            Some(_) => None,
+            None => Some(self.pitch as usize / 4),
+        }
+    }
+
     #[inline]
-    fn present_with_damage(self, damage: &[crate::Rect]) -> Result<(), SoftBufferError> {
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        self.frame.requested = true;
+        Ok(())
+    }
+
+    fn present_with_damage(mut self, damage: &[crate::Rect]) -> Result<(), SoftBufferError> {
         let rectangles = damage
             .iter()
             .map(|&rect| {
@@ -328,6 +623,28 @@ impl<D: ?Sized, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
             })
             .collect::<Result<Vec<_>, _>>()?;
 
+        if let Some(unpacked) = &self.unpacked {
+            // Only the damage needs packing if the dumb buffer holds the last frame.
+            let (width, height) = self.size;
+            let full = [crate::Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }];
+            let packed = if *self.front_age == 1 { damage } else { &full };
+
+            for rect in packed {
+                pack_rgb565(
+                    unpacked,
+                    width.get() as usize,
+                    self.mapping.as_mut(),
+                    self.pitch as usize,
+                    rect,
+                );
+            }
+        }
+
         // Dirty the framebuffer with out damage rectangles.
         //
         // Some drivers don't support this, so we just ignore the `ENOSYS` error.
@@ -345,11 +662,18 @@ impl<D: ?Sized, W: ?Sized> BufferInterface for BufferImpl<'_, D, W> {
             }
         }
 
+        if self.frame.requested {
+            self.display.forget_page_flips(self.crtc_handle)?;
+        }
+
         // Swap the buffers.
         // TODO: Use atomic commits here!
         self.display
-            .page_flip(self.crtc_handle, self.front_fb, PageFlipFlags::EVENT, None)
+            .page_flip(self.crtc_handle, self.front_fb, self.page_flip_flags, None)
             .swbuf_err("failed to page flip")?;
+        if std::mem::take(&mut self.frame.requested) {
+            self.frame.pending = true;
+        }
 
         // Flip the front and back buffers.
         *self.first_is_front = !*self.first_is_front;
@@ -381,13 +705,29 @@ impl SharedBuffer {
         display: &KmsDisplayImpl<D>,
         width: NonZeroU32,
         height: NonZeroU32,
+        format: PixelFormat,
     ) -> Result<Self, SoftBufferError> {
+        let fourcc = match format {
+            PixelFormat::Xrgb8888 => DrmFourcc::Xrgb8888,
+            PixelFormat::Xbgr8888 => DrmFourcc::Xbgr8888,
+            PixelFormat::Xrgb2101010 => DrmFourcc::Xrgb2101010,
+            PixelFormat::Rgb565 => DrmFourcc::Rgb565,
+        };
+        let bpp = if format == PixelFormat::Rgb565 {
+            16
+        } else {
+            32
+        };
         let db = display
-            .create_dumb_buffer((width.get(), height.get()), DrmFourcc::Xrgb8888, 32)
+            .create_dumb_buffer((width.get(), height.get()), fourcc, bpp)
             .swbuf_err("failed to create dumb buffer")?;
-        let fb = display
-            .add_framebuffer(&db, 24, 32)
-            .swbuf_err("failed to add framebuffer")?;
+        let fb = if format == PixelFormat::Xrgb8888 {
+            // The legacy call can only describe the default format, but works everywhere.
+            display.add_framebuffer(&db, 24, 32)
+        } else {
+            display.add_planar_framebuffer(&SinglePlane(&db), FbCmd2Flags::empty())
+        }
+        .swbuf_err("failed to add framebuffer")?;
 
         Ok(SharedBuffer { fb, db, age: 0 })
     }
@@ -408,3 +748,124 @@ impl Buffers {
         self.buffers[0].size()
     }
 }
+
+/// Pack the part of `src` covered by `rect` into a 16-bit buffer.
+/// The capabilities of a KMS surface, depending on whether the driver supports async page flips.
+fn capabilities(async_page_flip: bool) -> Capabilities {
+    Capabilities {
+        partial_damage: true,
+        max_buffer_age: 2,
+        // Presenting waits for the page flip, which happens on vertical blank.
+        frame_pacing: true,
+        present_modes: if async_page_flip {
+            vec![PresentMode::Vsync, PresentMode::Immediate]
+        } else {
+            vec![PresentMode::Vsync]
+        },
+        ..Capabilities::new()
+    }
+}
+
+fn pack_rgb565(src: &[u32], width: usize, dst: &mut [u8], pitch: usize, rect: &crate::Rect) {
+    let height = src.len() / width;
+
+    // Damage outside of the buffer has nothing to pack.
+    let left = (rect.x as usize).min(width);
+    let right = (rect.x as usize)
+        .saturating_add(rect.width.get() as usize)
+        .min(width);
+    let top = (rect.y as usize).min(height);
+    let bottom = (rect.y as usize)
+        .saturating_add(rect.height.get() as usize)
+        .min(height);
+
+    for y in top..bottom {
+        let row = &mut dst[y * pitch..][..width * 2];
+        let row: &mut [u16] = bytemuck::cast_slice_mut(row);
+        for (dst, &src) in row[left..right].iter_mut().zip(&src[y * width + left..]) {
+            *dst = src as u16;
+        }
+    }
+}
+
+/// A dumb buffer described as a buffer with a single plane.
+struct SinglePlane<'a>(&'a DumbBuffer);
+
+impl PlanarBuffer for SinglePlane<'_> {
+    fn size(&self) -> (u32, u32) {
+        self.0.size()
+    }
+
+    fn format(&self) -> DrmFourcc {
+        self.0.format()
+    }
+
+    fn modifier(&self) -> Option<DrmModifier> {
+        None
+    }
+
+    fn pitches(&self) -> [u32; 4] {
+        [self.0.pitch(), 0, 0, 0]
+    }
+
+    fn handles(&self) -> [Option<Handle>; 4] {
+        [Some(self.0.handle()), None, None, None]
+    }
+
+    fn offsets(&self) -> [u32; 4] {
+        [0; 4]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_report_frame_pacing() {
+        for async_page_flip in [false, true] {
+            let capabilities = capabilities(async_page_flip);
+            assert!(capabilities.frame_pacing);
+            assert_eq!(
+                capabilities.present_modes.contains(&PresentMode::Immediate),
+                async_page_flip
+            );
+        }
+    }
+
+    /// A `drm_event_vblank` for a finished page flip of `crtc`.
+    #[cfg(feature = "async")]
+    fn page_flip(crtc: u32) -> Vec<u8> {
+        [2, 32, 0, 0, 0, 0, 0, crtc]
+            .iter()
+            .flat_map(|word: &u32| word.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn frame_waiter_skips_earlier_flips() {
+        use crate::reactor::FrameWait;
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::net::UnixStream;
+
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let display = KmsDisplayImpl {
+            // SAFETY: `reader` outlives the display.
+            fd: unsafe { BorrowedFd::borrow_raw(reader.as_raw_fd()) },
+            flipped: Mutex::default(),
+            _display: (),
+        };
+        let crtc = crtc::Handle::from(NonZeroU32::new(7).unwrap());
+
+        // The flip of a `present()` that nothing waited for, still unread.
+        writer.write_all(&page_flip(7)).unwrap();
+
+        // `present_async()` waits for its own flip.
+        let mut waiter = FrameWaiter::new(&display, crtc).unwrap();
+        assert!(!waiter.is_done().unwrap());
+        writer.write_all(&page_flip(7)).unwrap();
+        assert!(waiter.is_done().unwrap());
+    }
+}
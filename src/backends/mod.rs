@@ -1,8 +1,11 @@
 use crate::{ContextInterface, InitError};
 use raw_window_handle::HasDisplayHandle;
+#[cfg(any(wayland_platform, kms_platform, x11_platform))]
+use std::{io, os::fd::BorrowedFd, time::Duration};
 
 #[cfg(target_os = "macos")]
 pub(crate) mod cg;
+pub(crate) mod headless;
 #[cfg(kms_platform)]
 pub(crate) mod kms;
 #[cfg(target_os = "redox")]
@@ -21,3 +24,48 @@ impl<D: HasDisplayHandle> ContextInterface<D> for D {
         Ok(display)
     }
 }
+
+/// Wait until `fd` is readable, for at most `timeout` if it is set.
+///
+/// Returns `false` if the timeout ran out first.
+#[cfg(any(wayland_platform, kms_platform, x11_platform))]
+pub(crate) fn poll_readable(fd: BorrowedFd<'_>, timeout: Option<Duration>) -> io::Result<bool> {
+    use rustix::event::{poll, PollFd, PollFlags};
+
+    // Round up to whole milliseconds, so that short timeouts don't turn into busy loops.
+    let timeout = match timeout {
+        Some(timeout) => {
+            i32::try_from((timeout.as_nanos() + 999_999) / 1_000_000).unwrap_or(i32::MAX)
+        }
+        None => -1,
+    };
+
+    let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+    loop {
+        match poll(&mut fds, timeout) {
+            Ok(ready) => return Ok(ready > 0),
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[cfg(all(test, any(wayland_platform, kms_platform, x11_platform)))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn poll_readable_times_out() {
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        let timeout = Some(Duration::from_millis(1));
+        assert!(!poll_readable(reader.as_fd(), Some(Duration::ZERO)).unwrap());
+        assert!(!poll_readable(reader.as_fd(), timeout).unwrap());
+
+        writer.write_all(&[0]).unwrap();
+        assert!(poll_readable(reader.as_fd(), timeout).unwrap());
+        assert!(poll_readable(reader.as_fd(), None).unwrap());
+    }
+}
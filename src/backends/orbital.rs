@@ -1,6 +1,6 @@
-use crate::error::InitError;
+use crate::error::{InitError, SwResultExt};
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle, OrbitalWindowHandle, RawWindowHandle};
-use std::{cmp, marker::PhantomData, num::NonZeroU32, slice, str};
+use std::{cmp, io, marker::PhantomData, num::NonZeroU32, slice, str};
 
 use crate::backend_interface::*;
 use crate::{Rect, SoftBufferError};
@@ -46,11 +46,18 @@ impl OrbitalMap {
     }
 }
 
+/// Convert a Redox error into an `io::Error`, which carries the same `errno`.
+fn io_err(err: syscall::Error) -> io::Error {
+    io::Error::from_raw_os_error(err.errno)
+}
+
 impl Drop for OrbitalMap {
     fn drop(&mut self) {
         unsafe {
             // Unmap window buffer on drop
-            syscall::funmap(self.address, self.size).expect("failed to unmap orbital window");
+            if let Err(err) = syscall::funmap(self.address, self.size) {
+                log::error!("failed to unmap orbital window: {}", err);
+            }
         }
     }
 }
@@ -74,13 +81,15 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> OrbitalImpl<D, W> {
     }
 
     // Read the current width and size
-    fn window_size(&self) -> (usize, usize) {
+    fn window_size(&self) -> Result<(usize, usize), SoftBufferError> {
         let mut window_width = 0;
         let mut window_height = 0;
 
         let mut buf: [u8; 4096] = [0; 4096];
-        let count = syscall::fpath(self.window_fd(), &mut buf).unwrap();
-        let path = str::from_utf8(&buf[..count]).unwrap();
+        let count = syscall::fpath(self.window_fd(), &mut buf)
+            .map_err(io_err)
+            .swbuf_err("failed to get orbital window path")?;
+        let path = str::from_utf8(&buf[..count]).swbuf_err("orbital window path is not UTF-8")?;
         // orbital:/x/y/w/h/t
         let mut parts = path.split('/').skip(3);
         if let Some(w) = parts.next() {
@@ -90,18 +99,24 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> OrbitalImpl<D, W> {
             window_height = h.parse::<usize>().unwrap_or(0);
         }
 
-        (window_width, window_height)
+        Ok((window_width, window_height))
     }
 
-    fn set_buffer(&self, buffer: &[u32], width_u32: u32, height_u32: u32) {
+    fn set_buffer(
+        &self,
+        buffer: &[u32],
+        width_u32: u32,
+        height_u32: u32,
+    ) -> Result<(), SoftBufferError> {
         // Read the current width and size
-        let (window_width, window_height) = self.window_size();
+        let (window_width, window_height) = self.window_size()?;
 
         {
             // Map window buffer
             let window_map =
                 unsafe { OrbitalMap::new(self.window_fd(), window_width * window_height * 4) }
-                    .expect("failed to map orbital window");
+                    .map_err(io_err)
+                    .swbuf_err("failed to map orbital window")?;
 
             // Window buffer is u32 color data in 0xAABBGGRR format
             let window_data = unsafe { window_map.data_mut() };
@@ -122,7 +137,11 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> OrbitalImpl<D, W> {
         }
 
         // Tell orbital to show the latest window data
-        syscall::fsync(self.window_fd()).expect("failed to sync orbital window");
+        syscall::fsync(self.window_fd())
+            .map_err(io_err)
+            .swbuf_err("failed to sync orbital window")?;
+
+        Ok(())
     }
 }
 
@@ -164,12 +183,13 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for Orbital
     }
 
     fn buffer_mut(&mut self) -> Result<BufferImpl<'_, D, W>, SoftBufferError> {
-        let (window_width, window_height) = self.window_size();
+        let (window_width, window_height) = self.window_size()?;
         let pixels = if self.width as usize == window_width && self.height as usize == window_height
         {
             Pixels::Mapping(
                 unsafe { OrbitalMap::new(self.window_fd(), window_width * window_height * 4) }
-                    .expect("failed to map orbital window"),
+                    .map_err(io_err)
+                    .swbuf_err("failed to map orbital window")?,
             )
         } else {
             Pixels::Buffer(vec![0; self.width as usize * self.height as usize])
@@ -216,12 +236,14 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferInterface for BufferImpl
         match self.pixels {
             Pixels::Mapping(mapping) => {
                 drop(mapping);
-                syscall::fsync(self.imp.window_fd()).expect("failed to sync orbital window");
+                syscall::fsync(self.imp.window_fd())
+                    .map_err(io_err)
+                    .swbuf_err("failed to sync orbital window")?;
                 self.imp.presented = true;
             }
             Pixels::Buffer(buffer) => {
                 self.imp
-                    .set_buffer(&buffer, self.imp.width, self.imp.height);
+                    .set_buffer(&buffer, self.imp.width, self.imp.height)?;
             }
         }
 
@@ -2,6 +2,7 @@ use memmap2::MmapMut;
 use std::{
     ffi::CStr,
     fs::File,
+    io,
     os::unix::prelude::{AsFd, AsRawFd},
     slice,
     sync::{
@@ -15,22 +16,46 @@ use wayland_client::{
 };
 
 use super::State;
+use crate::util;
+#[cfg(any(test, not(any(target_os = "linux", target_os = "freebsd"))))]
+use std::{ffi::CString, os::fd::OwnedFd};
 
 #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-fn create_memfile() -> File {
+fn create_memfile() -> io::Result<File> {
     use rustix::fs::{MemfdFlags, SealFlags};
 
+    util::check_fault("memfd_create")?;
+
     let name = unsafe { CStr::from_bytes_with_nul_unchecked("softbuffer\0".as_bytes()) };
-    let fd = rustix::fs::memfd_create(name, MemfdFlags::CLOEXEC | MemfdFlags::ALLOW_SEALING)
-        .expect("Failed to create memfd to store buffer.");
-    rustix::fs::fcntl_add_seals(&fd, SealFlags::SHRINK | SealFlags::SEAL)
-        .expect("Failed to seal memfd.");
-    File::from(fd)
+    let fd = rustix::fs::memfd_create(name, MemfdFlags::CLOEXEC | MemfdFlags::ALLOW_SEALING)?;
+    rustix::fs::fcntl_add_seals(&fd, SealFlags::SHRINK | SealFlags::SEAL)?;
+    Ok(File::from(fd))
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
-fn create_memfile() -> File {
-    use rustix::{fs::Mode, io::Errno, shm::ShmOFlags};
+fn create_memfile() -> io::Result<File> {
+    use rustix::{fs::Mode, shm::ShmOFlags};
+
+    util::check_fault("shm_open")?;
+
+    let (fd, name) = open_unique_shm(|name| {
+        // `CLOEXEC` is implied with `shm_open`
+        rustix::shm::shm_open(
+            name,
+            ShmOFlags::RDWR | ShmOFlags::CREATE | ShmOFlags::EXCL,
+            Mode::RWXU,
+        )
+    })?;
+    let _ = rustix::shm::shm_unlink(&*name);
+    Ok(File::from(fd))
+}
+
+/// Open a shared memory object under a random name that isn't taken yet.
+#[cfg(any(test, not(any(target_os = "linux", target_os = "freebsd"))))]
+fn open_unique_shm(
+    mut open: impl FnMut(&CStr) -> rustix::io::Result<OwnedFd>,
+) -> io::Result<(OwnedFd, CString)> {
+    use rustix::io::Errno;
     use std::iter;
 
     // Use a cached RNG to avoid hammering the thread local.
@@ -39,32 +64,38 @@ fn create_memfile() -> File {
     for _ in 0..=4 {
         let mut name = String::from("softbuffer-");
         name.extend(iter::repeat_with(|| rng.alphanumeric()).take(7));
-        name.push('\0');
+        let name = CString::new(name).expect("the name has no nul bytes");
 
-        let name = unsafe { CStr::from_bytes_with_nul_unchecked(name.as_bytes()) };
-        // `CLOEXEC` is implied with `shm_open`
-        let fd = rustix::shm::shm_open(
-            name,
-            ShmOFlags::RDWR | ShmOFlags::CREATE | ShmOFlags::EXCL,
-            Mode::RWXU,
-        );
-        if !matches!(fd, Err(Errno::EXIST)) {
-            let fd = fd.expect("Failed to create POSIX shm to store buffer.");
-            let _ = rustix::shm::shm_unlink(name);
-            return File::from(fd);
+        match open(&name) {
+            Err(Errno::EXIST) => continue,
+            fd => return Ok((fd?, name)),
         }
     }
 
-    panic!("Failed to generate non-existent shm name")
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "failed to generate non-existent shm name",
+    ))
 }
 
 // Round size to use for pool for given dimensions, rounding up to power of 2
-fn get_pool_size(width: i32, height: i32) -> i32 {
-    ((width * height * 4) as u32).next_power_of_two() as i32
+fn get_pool_size(width: i32, height: i32) -> io::Result<i32> {
+    width
+        .checked_mul(height)
+        .and_then(|len| len.checked_mul(4))
+        .and_then(|len| (len as u32).checked_next_power_of_two())
+        .and_then(|len| i32::try_from(len).ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Dimensions are too large: ({width} x {height})"),
+            )
+        })
 }
 
-unsafe fn map_file(file: &File) -> MmapMut {
-    unsafe { MmapMut::map_mut(file.as_raw_fd()).expect("Failed to map shared memory") }
+unsafe fn map_file(file: &File) -> io::Result<MmapMut> {
+    util::check_fault("mmap")?;
+    unsafe { MmapMut::map_mut(file.as_raw_fd()) }
 }
 
 pub(super) struct WaylandBuffer {
@@ -76,34 +107,33 @@ pub(super) struct WaylandBuffer {
     buffer: wl_buffer::WlBuffer,
     width: i32,
     height: i32,
+    format: wl_shm::Format,
     released: Arc<AtomicBool>,
     pub age: u8,
 }
 
 impl WaylandBuffer {
-    pub fn new(shm: &wl_shm::WlShm, width: i32, height: i32, qh: &QueueHandle<State>) -> Self {
+    pub fn new(
+        shm: &wl_shm::WlShm,
+        width: i32,
+        height: i32,
+        format: wl_shm::Format,
+        qh: &QueueHandle<State>,
+    ) -> io::Result<Self> {
         // Calculate size to use for shm pool
-        let pool_size = get_pool_size(width, height);
+        let pool_size = get_pool_size(width, height)?;
 
         // Create an `mmap` shared memory
-        let tempfile = create_memfile();
-        let _ = tempfile.set_len(pool_size as u64);
-        let map = unsafe { map_file(&tempfile) };
+        let tempfile = create_memfile()?;
+        tempfile.set_len(pool_size as u64)?;
+        let map = unsafe { map_file(&tempfile)? };
 
         // Create wayland shm pool and buffer
         let pool = shm.create_pool(tempfile.as_fd(), pool_size, qh, ());
         let released = Arc::new(AtomicBool::new(true));
-        let buffer = pool.create_buffer(
-            0,
-            width,
-            height,
-            width * 4,
-            wl_shm::Format::Xrgb8888,
-            qh,
-            released.clone(),
-        );
+        let buffer = pool.create_buffer(0, width, height, width * 4, format, qh, released.clone());
 
-        Self {
+        Ok(Self {
             qh: qh.clone(),
             map,
             tempfile,
@@ -112,39 +142,43 @@ impl WaylandBuffer {
             buffer,
             width,
             height,
+            format,
             released,
             age: 0,
-        }
+        })
     }
 
-    pub fn resize(&mut self, width: i32, height: i32) {
+    pub fn resize(&mut self, width: i32, height: i32) -> io::Result<()> {
         // If size is the same, there's nothing to do
         if self.width != width || self.height != height {
-            // Destroy old buffer
-            self.buffer.destroy();
-
-            // Grow pool, if needed
-            let size = ((width * height * 4) as u32).next_power_of_two() as i32;
+            // Grow pool, if needed, before anything is changed, so that failing leaves this intact
+            let size = get_pool_size(width, height)?;
             if size > self.pool_size {
-                let _ = self.tempfile.set_len(size as u64);
+                self.tempfile.set_len(size as u64)?;
+                let map = unsafe { map_file(&self.tempfile)? };
                 self.pool.resize(size);
                 self.pool_size = size;
-                self.map = unsafe { map_file(&self.tempfile) };
+                self.map = map;
             }
 
+            // Destroy old buffer
+            self.buffer.destroy();
+
             // Create buffer with correct size
             self.buffer = self.pool.create_buffer(
                 0,
                 width,
                 height,
                 width * 4,
-                wl_shm::Format::Xrgb8888,
+                self.format,
                 &self.qh,
                 self.released.clone(),
             );
             self.width = width;
             self.height = height;
         }
+
+        Ok(())
     }
 
     pub fn attach(&self, surface: &wl_surface::WlSurface) {
@@ -152,14 +186,22 @@ impl WaylandBuffer {
         surface.attach(Some(&self.buffer), 0, 0);
     }
 
-    pub fn released(&self) -> bool {
-        self.released.load(Ordering::SeqCst)
+    pub fn released_flag(&self) -> &Arc<AtomicBool> {
+        &self.released
     }
 
     fn len(&self) -> usize {
         self.width as usize * self.height as usize
     }
 
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    pub unsafe fn mapped(&self) -> &[u32] {
+        unsafe { slice::from_raw_parts(self.map.as_ptr() as *const u32, self.len()) }
+    }
+
     pub unsafe fn mapped_mut(&mut self) -> &mut [u32] {
         unsafe { slice::from_raw_parts_mut(self.map.as_mut_ptr() as *mut u32, self.len()) }
     }
@@ -198,3 +240,40 @@ impl Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>> for State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustix::io::Errno;
+
+    #[test]
+    fn allocation_failures_are_errors() {
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        util::inject_fault("memfd_create", Errno::NOMEM.raw_os_error());
+        #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+        util::inject_fault("shm_open", Errno::NOMEM.raw_os_error());
+        let err = create_memfile().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+
+        let file = create_memfile().unwrap();
+        file.set_len(4096).unwrap();
+        util::inject_fault("mmap", Errno::NOMEM.raw_os_error());
+        assert!(unsafe { map_file(&file) }.is_err());
+        assert!(unsafe { map_file(&file) }.is_ok());
+
+        assert!(get_pool_size(i32::MAX, 2).is_err());
+        assert_eq!(get_pool_size(3, 5).unwrap(), 64);
+    }
+
+    #[test]
+    fn shm_name_collisions_are_errors() {
+        let mut attempts = 0;
+        let err = open_unique_shm(|_| {
+            attempts += 1;
+            Err(Errno::EXIST)
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(attempts, 5);
+    }
+}
@@ -1,17 +1,23 @@
 use crate::{
     backend_interface::*,
     error::{InitError, SwResultExt},
-    util, Rect, SoftBufferError,
+    util, AlphaMode, Capabilities, PixelFormat, Rect, SoftBufferError,
 };
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle};
 use std::{
-    num::{NonZeroI32, NonZeroU32},
-    sync::{Arc, Mutex},
+    io,
+    num::{NonZeroI32, NonZeroU32, NonZeroUsize},
+    os::fd::{AsFd, BorrowedFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use wayland_client::{
-    backend::{Backend, ObjectId},
+    backend::{Backend, ObjectId, WaylandError},
     globals::{registry_queue_init, GlobalListContents},
-    protocol::{wl_registry, wl_shm, wl_surface},
+    protocol::{wl_callback, wl_registry, wl_shm, wl_surface},
     Connection, Dispatch, EventQueue, Proxy, QueueHandle,
 };
 
@@ -26,6 +32,12 @@ pub struct WaylandDisplayImpl<D: ?Sized> {
     qh: QueueHandle<State>,
     shm: wl_shm::WlShm,
 
+    /// The `wl_shm` formats the compositor supports.
+    shm_formats: Vec<wl_shm::Format>,
+
+    /// The formats the compositor supports, best first.
+    formats: Vec<PixelFormat>,
+
     /// The object that owns the display handle.
     ///
     /// This has to be dropped *after* the `conn` field, because the `conn` field implicitly borrows
@@ -52,20 +64,61 @@ impl<D: HasDisplayHandle + ?Sized> ContextInterface<D> for Arc<WaylandDisplayImp
 
         let backend = unsafe { Backend::from_foreign_display(wayland_handle.as_ptr().cast()) };
         let conn = Connection::from_backend(backend);
-        let (globals, event_queue) =
+        let (globals, mut event_queue) =
             registry_queue_init(&conn).swbuf_err("Failed to make round trip to server")?;
         let qh = event_queue.handle();
         let shm: wl_shm::WlShm = globals
-            .bind(&qh, 1..=1, ())
+            .bind(&qh, 1..=1, Mutex::new(Vec::new()))
             .swbuf_err("Failed to instantiate Wayland Shm")?;
+
+        // The supported formats are sent right after binding.
+        event_queue
+            .roundtrip(&mut State)
+            .swbuf_err("Failed to make round trip to server")?;
+        let mut shm_formats = std::mem::take(
+            &mut *shm
+                .data::<Mutex<Vec<wl_shm::Format>>>()
+                .unwrap()
+                .lock()
+                .unwrap_or_else(|x| x.into_inner()),
+        );
+        // Every compositor supports these two, even if it doesn't announce them.
+        shm_formats.extend([wl_shm::Format::Xrgb8888, wl_shm::Format::Argb8888]);
+
+        // `Xrgb8888` comes first, as the default is the preferred format.
+        let formats = PixelFormat::ALL
+            .iter()
+            .copied()
+            .filter(|&format| {
+                wl_format(format, AlphaMode::Opaque)
+                    .is_some_and(|format| shm_formats.contains(&format))
+            })
+            .collect();
+
         Ok(Arc::new(WaylandDisplayImpl {
             conn: Some(conn),
             event_queue: Mutex::new(event_queue),
             qh,
             shm,
+            shm_formats,
+            formats,
             _display: display,
         }))
     }
+
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        Some(self.conn().as_fd())
+    }
+
+    fn dispatch_pending(&self) -> Result<(), SoftBufferError> {
+        // Nothing sets the flag, so this reads until no more events are ready.
+        dispatch_until_released(
+            &self.event_queue,
+            &AtomicBool::new(false),
+            Some(Duration::ZERO),
+        )
+        .map(drop)
+    }
 }
 
 impl<D: ?Sized> Drop for WaylandDisplayImpl<D> {
@@ -80,6 +133,15 @@ pub struct WaylandImpl<D: ?Sized, W: ?Sized> {
     surface: Option<wl_surface::WlSurface>,
     buffers: Option<(WaylandBuffer, WaylandBuffer)>,
     size: Option<(NonZeroI32, NonZeroI32)>,
+    format: PixelFormat,
+    alpha_mode: AlphaMode,
+    shm_format: wl_shm::Format,
+
+    /// Whether to ask for a frame callback with the next commit.
+    frame_requested: bool,
+
+    /// Set by the frame callback that was asked for last, until the frame is done.
+    frame_done: Option<Arc<AtomicBool>>,
 
     /// The pointer to the window object.
     ///
@@ -133,6 +195,12 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> WaylandImpl<D, W> {
                 }
             }
 
+            if std::mem::take(&mut self.frame_requested) {
+                let done = Arc::new(AtomicBool::new(false));
+                self.surface().frame(&self.display.qh, done.clone());
+                self.frame_done = Some(done);
+            }
+
             self.surface().commit();
         }
 
@@ -175,6 +243,11 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W>
             surface: Some(surface),
             buffers: Default::default(),
             size: None,
+            format: PixelFormat::default(),
+            alpha_mode: AlphaMode::default(),
+            shm_format: wl_shm::Format::Xrgb8888,
+            frame_requested: false,
+            frame_done: None,
             window_handle: window,
         })
     }
@@ -184,6 +257,17 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W>
         &self.window_handle
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // `damage_buffer` was only added in version 4.
+            partial_damage: self.surface().version() >= 4,
+            max_buffer_age: 2,
+            frame_pacing: true,
+            ..Capabilities::new()
+        }
+        .with_max_size(i32::MAX as u32)
+    }
+
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
         self.size = Some(
             (|| {
@@ -197,56 +281,173 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W>
     }
 
     fn buffer_mut(&mut self) -> Result<BufferImpl<'_, D, W>, SoftBufferError> {
-        let (width, height) = self
-            .size
-            .expect("Must set size of surface before calling `buffer_mut()`");
+        let (width, height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
 
-        if let Some((_front, back)) = &mut self.buffers {
-            // Block if back buffer not released yet
-            if !back.released() {
-                let mut event_queue = self
-                    .display
-                    .event_queue
-                    .lock()
-                    .unwrap_or_else(|x| x.into_inner());
-                while !back.released() {
-                    event_queue.blocking_dispatch(&mut State).map_err(|err| {
-                        SoftBufferError::PlatformError(
-                            Some("Wayland dispatch failure".to_string()),
-                            Some(Box::new(err)),
-                        )
-                    })?;
-                }
-            }
+        // Block if back buffer not released yet
+        self.wait_for_buffer(None)?;
 
+        if let Some((_front, back)) = &mut self.buffers {
             // Resize, if buffer isn't large enough
-            back.resize(width.get(), height.get());
+            back.resize(width.get(), height.get())
+                .swbuf_err("Failed to resize Wayland buffer")?;
         } else {
             // Allocate front and back buffer
-            self.buffers = Some((
-                WaylandBuffer::new(
-                    &self.display.shm,
-                    width.get(),
-                    height.get(),
-                    &self.display.qh,
-                ),
+            let format = self.shm_format;
+            let new_buffer = || {
                 WaylandBuffer::new(
                     &self.display.shm,
                     width.get(),
                     height.get(),
+                    format,
                     &self.display.qh,
-                ),
-            ));
+                )
+                .swbuf_err("Failed to allocate Wayland buffer")
+            };
+            self.buffers = Some((new_buffer()?, new_buffer()?));
         };
 
         let age = self.buffers.as_mut().unwrap().1.age;
+        #[cfg(feature = "async")]
+        let display = self.display.clone();
         Ok(BufferImpl {
-            stack: util::BorrowStack::new(self, |buffer| {
-                Ok(unsafe { buffer.buffers.as_mut().unwrap().1.mapped_mut() })
-            })?,
+            stack: util::BorrowStack::new(self, |buffer| Ok(buffer.buffers.as_mut().unwrap()))?,
             age,
+            frame_requested: false,
+            #[cfg(feature = "async")]
+            display,
         })
     }
+
+    fn supported_formats(&self) -> &[PixelFormat] {
+        &self.display.formats
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        self.set_shm_format(format, self.alpha_mode)
+    }
+
+    fn set_alpha_mode(&mut self, mode: AlphaMode) -> Result<(), SoftBufferError> {
+        self.set_shm_format(self.format, mode)
+    }
+
+    fn set_buffer_count(&mut self, count: NonZeroUsize) -> Result<(), SoftBufferError> {
+        if count.get() == 2 {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+
+    fn wait_for_buffer(&mut self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+        match &self.buffers {
+            Some((_front, back)) => {
+                dispatch_until_released(&self.display.event_queue, back.released_flag(), timeout)
+            }
+            None => Ok(true),
+        }
+    }
+
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.display.readiness_fd()
+    }
+
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        self.frame_requested = true;
+        Ok(())
+    }
+
+    fn frame_ready(&mut self) -> Result<bool, SoftBufferError> {
+        let Some(done) = &self.frame_done else {
+            return Ok(true);
+        };
+        if !dispatch_until_released(&self.display.event_queue, done, Some(Duration::ZERO))? {
+            return Ok(false);
+        }
+        self.frame_done = None;
+        Ok(true)
+    }
+}
+
+impl<D: ?Sized, W: ?Sized> WaylandImpl<D, W> {
+    /// Switch to the `wl_shm` format for `format` and `alpha_mode`, if the compositor has it.
+    fn set_shm_format(
+        &mut self,
+        format: PixelFormat,
+        alpha_mode: AlphaMode,
+    ) -> Result<(), SoftBufferError> {
+        let shm_format = wl_format(format, alpha_mode)
+            .filter(|shm_format| self.display.shm_formats.contains(shm_format))
+            .ok_or(SoftBufferError::Unimplemented)?;
+
+        if shm_format != self.shm_format {
+            // The buffers are reallocated in the new format on the next `buffer_mut`.
+            self.buffers = None;
+            self.shm_format = shm_format;
+        }
+        self.format = format;
+        self.alpha_mode = alpha_mode;
+        Ok(())
+    }
+}
+
+/// Dispatch events until `released` is set, for at most `timeout` if it is set.
+///
+/// Returns `false` if the timeout ran out first.
+fn dispatch_until_released(
+    event_queue: &Mutex<EventQueue<State>>,
+    released: &AtomicBool,
+    timeout: Option<Duration>,
+) -> Result<bool, SoftBufferError> {
+    if released.load(Ordering::SeqCst) {
+        return Ok(true);
+    }
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    let mut event_queue = event_queue.lock().unwrap_or_else(|x| x.into_inner());
+    while !released.load(Ordering::SeqCst) {
+        // This is `blocking_dispatch`, except that reading gives up at the deadline.
+        if event_queue
+            .dispatch_pending(&mut State)
+            .swbuf_err("Wayland dispatch failure")?
+            > 0
+        {
+            continue;
+        }
+        event_queue
+            .flush()
+            .swbuf_err("Failed to flush Wayland connection")?;
+
+        // There are events left to dispatch if another thread read them in the meantime.
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if !super::poll_readable(guard.connection_fd(), timeout)
+            .swbuf_err("Failed to poll Wayland connection")?
+        {
+            return Ok(false);
+        }
+        match guard.read() {
+            Ok(_) => {}
+            Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err).swbuf_err("Wayland dispatch failure"),
+        }
+    }
+
+    Ok(true)
+}
+
+/// The `wl_shm` format for a pixel format, if it uses 32 bits per pixel like our buffers.
+fn wl_format(format: PixelFormat, alpha_mode: AlphaMode) -> Option<wl_shm::Format> {
+    Some(match (format, alpha_mode) {
+        (PixelFormat::Xrgb8888, AlphaMode::Opaque) => wl_shm::Format::Xrgb8888,
+        (PixelFormat::Xrgb8888, AlphaMode::Premultiplied) => wl_shm::Format::Argb8888,
+        (PixelFormat::Xbgr8888, AlphaMode::Opaque) => wl_shm::Format::Xbgr8888,
+        (PixelFormat::Xbgr8888, AlphaMode::Premultiplied) => wl_shm::Format::Abgr8888,
+        (PixelFormat::Xrgb2101010, AlphaMode::Opaque) => wl_shm::Format::Xrgb2101010,
+        (PixelFormat::Xrgb2101010, AlphaMode::Premultiplied) => wl_shm::Format::Argb2101010,
+        (PixelFormat::Rgb565, _) => return None,
+    })
 }
 
 impl<D: ?Sized, W: ?Sized> Drop for WaylandImpl<D, W> {
@@ -257,8 +458,46 @@ impl<D: ?Sized, W: ?Sized> Drop for WaylandImpl<D, W> {
 }
 
 pub struct BufferImpl<'a, D: ?Sized, W> {
-    stack: util::BorrowStack<'a, WaylandImpl<D, W>, [u32]>,
+    /// The front and back buffer.
+    stack: util::BorrowStack<'a, WaylandImpl<D, W>, (WaylandBuffer, WaylandBuffer)>,
     age: u8,
+    frame_requested: bool,
+    #[cfg(feature = "async")]
+    display: Arc<WaylandDisplayImpl<D>>,
+}
+
+#[cfg(feature = "async")]
+impl<D: ?Sized, W> BufferImpl<'_, D, W> {
+    /// Wait for the compositor to release the front buffer, which is the back buffer once this
+    /// one is presented.
+    pub(crate) fn frame_waiter(&self) -> FrameWaiter<D> {
+        FrameWaiter {
+            display: self.display.clone(),
+            released: self.stack.member().0.released_flag().clone(),
+        }
+    }
+}
+
+/// Waits for a buffer to be released, see [`BufferImpl::frame_waiter`].
+#[cfg(feature = "async")]
+pub(crate) struct FrameWaiter<D: ?Sized> {
+    display: Arc<WaylandDisplayImpl<D>>,
+    released: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "async")]
+impl<D: HasDisplayHandle + ?Sized> crate::reactor::FrameWait for FrameWaiter<D> {
+    fn fd(&self) -> BorrowedFd<'_> {
+        self.display.conn().as_fd()
+    }
+
+    fn is_done(&mut self) -> Result<bool, SoftBufferError> {
+        dispatch_until_released(
+            &self.display.event_queue,
+            &self.released,
+            Some(Duration::ZERO),
+        )
+    }
 }
 
 impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle> BufferInterface
@@ -266,27 +505,50 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle> BufferInterface
 {
     #[inline]
     fn pixels(&self) -> &[u32] {
-        self.stack.member()
+        unsafe { self.stack.member().1.mapped() }
     }
 
     #[inline]
     fn pixels_mut(&mut self) -> &mut [u32] {
-        self.stack.member_mut()
+        unsafe { self.stack.member_mut().1.mapped_mut() }
     }
 
     fn age(&self) -> u8 {
         self.age
     }
 
+    fn copy_from_front(&mut self, rects: &[Rect]) -> bool {
+        let (front, back) = self.stack.member_mut();
+        if front.age == 0 || front.size() != back.size() {
+            return false;
+        }
+
+        // The compositor only reads from the front buffer, so it can be read at any time.
+        let stride = back.size().0 as usize;
+        util::copy_rects(
+            unsafe { front.mapped() },
+            unsafe { back.mapped_mut() },
+            stride,
+            rects,
+        );
+        true
+    }
+
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        self.frame_requested = true;
+        Ok(())
+    }
+
     fn present_with_damage(self, damage: &[Rect]) -> Result<(), SoftBufferError> {
-        self.stack.into_container().present_with_damage(damage)
+        let imp = self.stack.into_container();
+        imp.frame_requested |= self.frame_requested;
+        imp.present_with_damage(damage)
     }
 
     fn present(self) -> Result<(), SoftBufferError> {
         let imp = self.stack.into_container();
-        let (width, height) = imp
-            .size
-            .expect("Must set size of surface before calling `present()`");
+        imp.frame_requested |= self.frame_requested;
+        let (width, height) = imp.size.ok_or(SoftBufferError::SizeNotSet)?;
         imp.present_with_damage(&[Rect {
             x: 0,
             y: 0,
@@ -297,6 +559,21 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle> BufferInterface
     }
 }
 
+impl Dispatch<wl_callback::WlCallback, Arc<AtomicBool>> for State {
+    fn event(
+        _: &mut State,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        done: &Arc<AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<State>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            done.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
 impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
     fn event(
         _: &mut State,
@@ -310,14 +587,23 @@ impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
     }
 }
 
-impl Dispatch<wl_shm::WlShm, ()> for State {
+impl Dispatch<wl_shm::WlShm, Mutex<Vec<wl_shm::Format>>> for State {
     fn event(
         _: &mut State,
         _: &wl_shm::WlShm,
-        _: wl_shm::Event,
-        _: &(),
+        event: wl_shm::Event,
+        formats: &Mutex<Vec<wl_shm::Format>>,
         _: &Connection,
         _: &QueueHandle<State>,
     ) {
+        if let wl_shm::Event::Format {
+            format: wayland_client::WEnum::Value(format),
+        } = event
+        {
+            formats
+                .lock()
+                .unwrap_or_else(|x| x.into_inner())
+                .push(format);
+        }
     }
 }
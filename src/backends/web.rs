@@ -11,7 +11,9 @@ use web_sys::{OffscreenCanvas, OffscreenCanvasRenderingContext2d};
 
 use crate::backend_interface::*;
 use crate::error::{InitError, SwResultExt};
-use crate::{util, NoDisplayHandle, NoWindowHandle, Rect, SoftBufferError};
+use crate::{
+    util, Capabilities, NoDisplayHandle, NoWindowHandle, PixelFormat, Rect, SoftBufferError,
+};
 use std::marker::PhantomData;
 use std::num::NonZeroU32;
 
@@ -56,6 +58,9 @@ pub struct WebImpl<D, W> {
     /// The current canvas width/height.
     size: Option<(NonZeroU32, NonZeroU32)>,
 
+    /// The format of the buffer.
+    format: PixelFormat,
+
     /// The underlying window handle.
     window_handle: W,
 
@@ -85,6 +90,7 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> WebImpl<D, W> {
             buffer: Vec::new(),
             buffer_presented: false,
             size: None,
+            format: PixelFormat::default(),
             window_handle: window,
             _display: PhantomData,
         })
@@ -101,6 +107,7 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> WebImpl<D, W> {
             buffer: Vec::new(),
             buffer_presented: false,
             size: None,
+            format: PixelFormat::default(),
             window_handle: window,
             _display: PhantomData,
         })
@@ -116,15 +123,23 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> WebImpl<D, W> {
                 "A canvas context other than `{name}` was already created"
             ))?
             .dyn_into()
-            .unwrap_or_else(|_| panic!("`getContext(\"2d\") didn't return a `{name}`"));
+            .ok()
+            .swbuf_err(format!("`getContext(\"2d\")` didn't return a `{name}`"))?;
 
         Ok(ctx)
     }
 
+    /// The shifts of the red and blue channels in the buffer's pixels.
+    fn channel_shifts(&self) -> (u32, u32) {
+        if self.format == PixelFormat::Xbgr8888 {
+            (0, 16)
+        } else {
+            (16, 0)
+        }
+    }
+
     fn present_with_damage(&mut self, damage: &[Rect]) -> Result<(), SoftBufferError> {
-        let (buffer_width, _buffer_height) = self
-            .size
-            .expect("Must set size of surface before calling `present_with_damage()`");
+        let (buffer_width, _buffer_height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
 
         let union_damage = if let Some(rect) = util::union_damage(damage) {
             rect
@@ -133,6 +148,7 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> WebImpl<D, W> {
         };
 
         // Create a bitmap from the buffer.
+        let (red_shift, blue_shift) = self.channel_shifts();
         let bitmap: Vec<_> = self
             .buffer
             .chunks_exact(buffer_width.get() as usize)
@@ -144,7 +160,14 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> WebImpl<D, W> {
                     .take(union_damage.width.get() as usize)
             })
             .copied()
-            .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 255])
+            .flat_map(|pixel| {
+                [
+                    (pixel >> red_shift) as u8,
+                    (pixel >> 8) as u8,
+                    (pixel >> blue_shift) as u8,
+                    255,
+                ]
+            })
             .collect();
 
         debug_assert_eq!(
@@ -244,12 +267,23 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for WebImpl
         &self.window_handle
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            fetch: true,
+            partial_damage: true,
+            ..Capabilities::new()
+        }
+    }
+
     /// De-duplicates the error handling between `HtmlCanvasElement` and `OffscreenCanvas`.
     /// Resize the canvas to the given dimensions.
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
         if self.size != Some((width, height)) {
+            let len = total_len(width.get(), height.get())
+                .ok_or(SoftBufferError::SizeOutOfRange { width, height })?;
+
             self.buffer_presented = false;
-            self.buffer.resize(total_len(width.get(), height.get()), 0);
+            self.buffer.resize(len, 0);
             self.canvas.set_width(width.get());
             self.canvas.set_height(height.get());
             self.size = Some((width, height));
@@ -263,9 +297,7 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for WebImpl
     }
 
     fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
-        let (width, height) = self
-            .size
-            .expect("Must set size of surface before calling `fetch()`");
+        let (width, height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
 
         let image_data = self
             .canvas
@@ -274,13 +306,31 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> SurfaceInterface<D, W> for WebImpl
             // TODO: Can also error if width or height are 0.
             .swbuf_err("`Canvas` contains pixels from a different origin")?;
 
+        let (red_shift, blue_shift) = self.channel_shifts();
         Ok(image_data
             .data()
             .0
             .chunks_exact(4)
-            .map(|chunk| u32::from_be_bytes([0, chunk[0], chunk[1], chunk[2]]))
+            .map(|chunk| {
+                (u32::from(chunk[0]) << red_shift)
+                    | (u32::from(chunk[1]) << 8)
+                    | (u32::from(chunk[2]) << blue_shift)
+            })
             .collect())
     }
+
+    fn supported_formats(&self) -> &[PixelFormat] {
+        &[PixelFormat::Xrgb8888, PixelFormat::Xbgr8888]
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        if !self.supported_formats().contains(&format) {
+            return Err(SoftBufferError::Unimplemented);
+        }
+
+        self.format = format;
+        Ok(())
+    }
 }
 
 /// Extension methods for the Wasm target on [`Surface`](crate::Surface).
@@ -303,10 +353,7 @@ impl SurfaceExtWeb for crate::Surface<NoDisplayHandle, NoWindowHandle> {
     fn from_canvas(canvas: HtmlCanvasElement) -> Result<Self, SoftBufferError> {
         let imple = crate::SurfaceDispatch::Web(WebImpl::from_canvas(canvas, NoWindowHandle(()))?);
 
-        Ok(Self {
-            surface_impl: Box::new(imple),
-            _marker: PhantomData,
-        })
+        Ok(Self::from_dispatch(imple))
     }
 
     fn from_offscreen_canvas(offscreen_canvas: OffscreenCanvas) -> Result<Self, SoftBufferError> {
@@ -315,10 +362,7 @@ impl SurfaceExtWeb for crate::Surface<NoDisplayHandle, NoWindowHandle> {
             NoWindowHandle(()),
         )?);
 
-        Ok(Self {
-            surface_impl: Box::new(imple),
-            _marker: PhantomData,
-        })
+        Ok(Self::from_dispatch(imple))
     }
 }
 
@@ -393,10 +437,7 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferInterface for BufferImpl
 
     /// Push the buffer to the canvas.
     fn present(self) -> Result<(), SoftBufferError> {
-        let (width, height) = self
-            .imp
-            .size
-            .expect("Must set size of surface before calling `present()`");
+        let (width, height) = self.imp.size.ok_or(SoftBufferError::SizeNotSet)?;
         self.imp.present_with_damage(&[Rect {
             x: 0,
             y: 0,
@@ -410,17 +451,12 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> BufferInterface for BufferImpl
     }
 }
 
+/// Returns `None` if the buffer doesn't fit into the address space.
 #[inline(always)]
-fn total_len(width: u32, height: u32) -> usize {
+fn total_len(width: u32, height: u32) -> Option<usize> {
     // Convert width and height to `usize`, then multiply.
     width
         .try_into()
         .ok()
         .and_then(|w: usize| height.try_into().ok().and_then(|h| w.checked_mul(h)))
-        .unwrap_or_else(|| {
-            panic!(
-                "Overflow when calculating total length of buffer: {}x{}",
-                width, height
-            );
-        })
 }
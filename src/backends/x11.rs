@@ -2,12 +2,20 @@
 //!
 //! This module converts the input buffer into an XImage and then sends it over the wire to be
 //! drawn by the X server. The SHM extension is used if available.
+//!
+//! Surfaces that ask for vsync or for more than one buffer use pixmaps in shared memory instead,
+//! which are shown with `PresentPixmap` requests if the Present extension is available. This
+//! doesn't tear, and the server tells when it is done with a pixmap and when it was shown.
 
 #![allow(clippy::uninlined_format_args)]
 
 use crate::backend_interface::*;
-use crate::error::{InitError, SwResultExt};
-use crate::{Rect, SoftBufferError};
+use crate::error::{platform_error, InitError, SwResultExt};
+use crate::{region, util};
+use crate::{
+    AlphaMode, Capabilities, ErrorKind, FrameTiming, PixelFormat, PresentMode, Rect,
+    SoftBufferError,
+};
 use raw_window_handle::{
     HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle, XcbDisplayHandle,
     XcbWindowHandle,
@@ -18,22 +26,27 @@ use rustix::{
 };
 
 use std::{
-    collections::HashSet,
+    borrow::Cow,
+    collections::HashMap,
     fmt,
     fs::File,
     io, mem,
-    num::{NonZeroU16, NonZeroU32},
+    num::{NonZeroU16, NonZeroU32, NonZeroUsize},
     ptr::{null_mut, NonNull},
     slice,
-    sync::Arc,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
 };
 
 use as_raw_xcb_connection::AsRawXcbConnection;
-use x11rb::connection::{Connection, SequenceNumber};
+use x11rb::connection::{Connection, RequestConnection as _, SequenceNumber};
 use x11rb::cookie::Cookie;
 use x11rb::errors::{ConnectionError, ReplyError, ReplyOrIdError};
+use x11rb::protocol::present::{self, ConnectionExt as _};
 use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xfixes::ConnectionExt as _;
 use x11rb::protocol::xproto::{self, ConnectionExt as _, ImageOrder, VisualClass, Visualid};
+use x11rb::protocol::Event;
 use x11rb::xcb_ffi::XCBConnection;
 
 pub struct X11DisplayImpl<D: ?Sized> {
@@ -43,8 +56,12 @@ pub struct X11DisplayImpl<D: ?Sized> {
     /// SHM extension is available.
     is_shm_available: bool,
 
-    /// All visuals using softbuffer's pixel representation
-    supported_visuals: HashSet<Visualid>,
+    /// All visuals using one of softbuffer's pixel formats, and that format
+    supported_visuals: HashMap<Visualid, PixelFormat>,
+
+    /// Our own connection for events, which is opened once a surface needs it and shared by the
+    /// surfaces of this context.
+    events: OnceLock<Option<Arc<EventConnection>>>,
 
     /// The generic display where the `connection` field comes from.
     ///
@@ -111,9 +128,21 @@ impl<D: HasDisplayHandle + ?Sized> ContextInterface<D> for Arc<X11DisplayImpl<D>
             connection: Some(connection),
             is_shm_available,
             supported_visuals,
+            events: OnceLock::new(),
             _display: display,
         }))
     }
+
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.events().map(|events| events.connection.as_fd())
+    }
+
+    fn dispatch_pending(&self) -> Result<(), SoftBufferError> {
+        match self.events.get() {
+            Some(Some(events)) => events.dispatch(),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl<D: ?Sized> X11DisplayImpl<D> {
@@ -122,6 +151,248 @@ impl<D: ?Sized> X11DisplayImpl<D> {
             .as_ref()
             .expect("X11DisplayImpl::connection() called after X11DisplayImpl::drop()")
     }
+
+    /// Get our own connection for events, opening it on first use.
+    fn events(&self) -> Option<&Arc<EventConnection>> {
+        self.events
+            .get_or_init(|| match EventConnection::new(self.connection()) {
+                Ok(events) => Some(Arc::new(events)),
+                Err(err) => {
+                    log::warn!("Failed to open an X11 connection for events: {}", err);
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+/// A connection of our own to the X server, for the events that we select.
+///
+/// Events are sent to the client that selected them, and `ShmCompletion` to the client that put
+/// the image. On the connection of the application, they would be read by whatever reads its
+/// events first, which is usually its event loop: libxcb can't take only some events out of the
+/// queue, and x11rb doesn't expose the special event queues that could hold the Present events.
+/// So the events are selected on this connection instead, and sorted by what they are for, so
+/// that each surface finds its own.
+///
+/// The display that the application connected to can't be looked up, so this connects to the one
+/// in `DISPLAY` and checks that it's the same server. Requests on this connection aren't ordered
+/// with the application's either, so `X11Impl::sync` waits for the server to handle those before
+/// a frame is sent from here.
+struct EventConnection {
+    /// The connection, to the same server as the application's.
+    connection: XCBConnection,
+
+    /// Present extension is available.
+    is_present_available: bool,
+
+    /// SHM extension is available.
+    is_shm_available: bool,
+
+    /// Pixmaps in SHM segments can be presented, which needs the Present and SHM extensions, and
+    /// XFixes for the regions that were updated.
+    is_pixmap_present_available: bool,
+
+    /// What the Present extension told us, for each Present event context.
+    windows: Mutex<HashMap<u32, PresentState>>,
+
+    /// The `shm::PutImage` requests whose `ShmCompletion` event is awaited, and whether the server
+    /// finished them, by the event or an error.
+    completions: Mutex<HashMap<SequenceNumber, bool>>,
+}
+
+/// What the Present extension told us about a window.
+#[derive(Default)]
+struct PresentState {
+    /// The serial of the last `CompleteNotify`.
+    completed: Option<u32>,
+
+    /// When the last presented pixmap was shown.
+    timing: Option<FrameTiming>,
+
+    /// The serial of the last pixmap that was shown, rather than skipped.
+    shown: Option<u32>,
+
+    /// The pixmaps that the server stopped reading from.
+    idle: Vec<xproto::Pixmap>,
+}
+
+impl EventConnection {
+    /// Connect to the server of the application's connection `app`.
+    fn new(app: &XCBConnection) -> Result<Self, SoftBufferError> {
+        // The display that a connection was opened for can't be looked up, so connect to the one
+        // in `DISPLAY` and check that it's the same.
+        let (connection, _) =
+            XCBConnection::connect(None).swbuf_err("Failed to spawn XCB connection")?;
+        if !is_same_server(app, &connection).swbuf_err("Failed to compare X11 connections")? {
+            return Err(platform_error(
+                ErrorKind::Unsupported,
+                "`DISPLAY` is another X server than the one of the display handle",
+            ));
+        }
+        let has_extension = |name| {
+            connection
+                .extension_information(name)
+                .swbuf_err("Failed to query X11 extensions")
+                .map(|info| info.is_some())
+        };
+
+        let is_present_available = has_extension(present::X11_EXTENSION_NAME)?
+            && connection
+                .present_query_version(1, 0)
+                .swbuf_err("Failed to send Present version request")?
+                .reply()
+                .is_ok();
+        if !is_present_available {
+            log::warn!("Present extension is not available. Frames will not be paced.");
+        }
+
+        let is_shm_available = is_shm_available(&connection);
+
+        // The server rejects XFixes requests until the version is agreed on.
+        let is_pixmap_present_available = is_present_available
+            && is_shm_available
+            && connection
+                .xfixes_query_version(2, 0)
+                .swbuf_err("Failed to send XFixes version request")?
+                .reply()
+                .is_ok_and(|reply| reply.major_version >= 2);
+
+        Ok(Self {
+            connection,
+            is_present_available,
+            is_shm_available,
+            is_pixmap_present_available,
+            windows: Mutex::default(),
+            completions: Mutex::default(),
+        })
+    }
+
+    /// Select the Present events for `window`, returning the new event context.
+    fn select_present_events(&self, window: xproto::Window) -> Result<u32, SoftBufferError> {
+        let eid = self
+            .connection
+            .generate_id()
+            .swbuf_err("Failed to generate Present event ID")?;
+        self.connection
+            .present_select_input(
+                eid,
+                window,
+                present::EventMask::COMPLETE_NOTIFY | present::EventMask::IDLE_NOTIFY,
+            )
+            .swbuf_err("Failed to send Present event selection")?
+            .check()
+            .swbuf_err("Failed to select Present events")?;
+        self.state(eid, |_| ());
+        Ok(eid)
+    }
+
+    /// Stop the events of the event context `eid` of `window`.
+    fn deselect_present_events(&self, eid: u32, window: xproto::Window) {
+        if let Ok(token) =
+            self.connection
+                .present_select_input(eid, window, present::EventMask::NO_EVENT)
+        {
+            token.ignore_error();
+        }
+        let _ = self.connection.flush();
+        self.windows
+            .lock()
+            .unwrap_or_else(|x| x.into_inner())
+            .remove(&eid);
+    }
+
+    /// Access what the Present extension told us about the event context `eid`.
+    fn state<R>(&self, eid: u32, f: impl FnOnce(&mut PresentState) -> R) -> R {
+        let mut windows = self.windows.lock().unwrap_or_else(|x| x.into_inner());
+        f(windows.entry(eid).or_default())
+    }
+
+    /// Handle the events that arrived, without blocking.
+    fn dispatch(&self) -> Result<(), SoftBufferError> {
+        while let Some((event, sequence)) = self
+            .connection
+            .poll_for_event_with_sequence()
+            .swbuf_err("Failed to read X11 events")?
+        {
+            self.handle(event, sequence);
+        }
+        Ok(())
+    }
+
+    /// Wait for events, for at most `timeout` if it is set, and handle them.
+    ///
+    /// Returns `false` if the timeout ran out first.
+    fn wait(&self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+        self.connection
+            .flush()
+            .swbuf_err("Failed to flush X11 connection")?;
+        if !super::poll_readable(self.connection.as_fd(), timeout)
+            .swbuf_err("Failed to poll X11 connection")?
+        {
+            return Ok(false);
+        }
+        self.dispatch()?;
+        Ok(true)
+    }
+
+    /// Send a `shm::PutImage` request with `send`, and remember to wait for its `ShmCompletion`.
+    fn send_awaited<E>(
+        &self,
+        send: impl FnOnce(&XCBConnection) -> Result<SequenceNumber, E>,
+    ) -> Result<SequenceNumber, E> {
+        // The lock keeps `handle` from seeing the event before the request is known.
+        let mut completions = self.completions.lock().unwrap_or_else(|x| x.into_inner());
+        let sequence = send(&self.connection)?;
+        completions.insert(sequence, false);
+        Ok(sequence)
+    }
+
+    /// Check if the `shm::PutImage` request `sequence` is finished, and forget it if so.
+    fn take_finished(&self, sequence: SequenceNumber) -> bool {
+        let mut completions = self.completions.lock().unwrap_or_else(|x| x.into_inner());
+        let finished = completions.get(&sequence).copied().unwrap_or(true);
+        if finished {
+            completions.remove(&sequence);
+        }
+        finished
+    }
+
+    /// Mark the request `sequence` as finished, if it is awaited.
+    fn finish(&self, sequence: SequenceNumber) {
+        let mut completions = self.completions.lock().unwrap_or_else(|x| x.into_inner());
+        if let Some(finished) = completions.get_mut(&sequence) {
+            *finished = true;
+        }
+    }
+
+    fn handle(&self, event: Event, sequence: SequenceNumber) {
+        match event {
+            Event::PresentCompleteNotify(event) => self.state(event.event, |state| {
+                state.completed = Some(event.serial);
+                if event.kind == present::CompleteKind::PIXMAP
+                    && event.mode != present::CompleteMode::SKIP
+                {
+                    state.timing = Some(FrameTiming {
+                        time: Duration::from_micros(event.ust),
+                        msc: event.msc,
+                    });
+                    state.shown = Some(event.serial);
+                }
+            }),
+            Event::PresentIdleNotify(event) => {
+                self.state(event.event, |state| state.idle.push(event.pixmap))
+            }
+            // The event is sent while the server handles the request, so it has its sequence.
+            Event::ShmCompletion(_) => self.finish(sequence),
+            Event::Error(err) => {
+                log::warn!("X11 error on the event connection: {:?}", err);
+                // A failed `shm::PutImage` request never gets its `ShmCompletion`.
+                self.finish(sequence);
+            }
+            _ => {}
+        }
+    }
 }
 
 /// The handle to an X11 drawing context.
@@ -141,12 +412,33 @@ pub struct X11Impl<D: ?Sized, W: ?Sized> {
     /// The visual ID of the drawing context.
     visual_id: u32,
 
+    /// The pixel format of the visual.
+    format: PixelFormat,
+
+    /// How the top byte of the pixels is treated.
+    alpha_mode: AlphaMode,
+
     /// The buffer we draw to.
     buffer: Buffer,
 
+    /// Our own connection, once the surface needed it for timeouts or presenting.
+    events: Option<Arc<EventConnection>>,
+
     /// Buffer has been presented.
     buffer_presented: bool,
 
+    /// The Present event context that selects `CompleteNotify` for the window, once needed.
+    present_event: Option<u32>,
+
+    /// Whether to ask for the next vertical blank after the next present.
+    frame_requested: bool,
+
+    /// The serial of the last Present request.
+    present_serial: u32,
+
+    /// The serial of the Present request that `frame_ready` waits for.
+    frame_pending: Option<u32>,
+
     /// The current buffer width/height.
     size: Option<(NonZeroU16, NonZeroU16)>,
 
@@ -161,13 +453,30 @@ enum Buffer {
 
     /// A normal buffer that we send over the wire.
     Wire(Vec<u32>),
+
+    /// Pixmaps in shared memory that are shown with the Present extension.
+    Present(PixmapRing),
 }
 
 struct ShmBuffer {
     /// The shared memory segment, paired with its ID.
     seg: Option<(ShmSegment, shm::Seg)>,
 
-    /// A cookie indicating that the shared memory segment is ready to be used.
+    /// Our own connection, if the window can be drawn to from it.
+    ///
+    /// The segment is attached and drawn from there then, and the last `shm::PutImage` request
+    /// asks for a `ShmCompletion` event. That tells us when the server is done with the segment,
+    /// without waiting for a round trip.
+    events: Option<Arc<EventConnection>>,
+
+    /// The rectangles of the segment whose alpha was made opaque for the server.
+    opaque_rects: Vec<Rect>,
+
+    /// The sequence number of the `shm::PutImage` request whose `ShmCompletion` event is awaited.
+    completion: Option<SequenceNumber>,
+
+    /// A cookie indicating that the shared memory segment is ready to be used, without our own
+    /// connection.
     ///
     /// We can't soundly read from or write to the SHM segment until the X server is done processing the
     /// `shm::PutImage` request. However, the X server handles requests in order, which means that, if
@@ -265,23 +574,25 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
             (geometry_reply, visual_id)
         };
 
-        if !display.supported_visuals.contains(&visual_id) {
-            return Err(SoftBufferError::PlatformError(
-                Some(format!(
-                    "Visual 0x{visual_id:x} does not use softbuffer's pixel format and is unsupported"
-                )),
-                None,
-            )
-            .into());
-        }
+        let format = match display.supported_visuals.get(&visual_id) {
+            Some(&format) => format,
+            None => {
+                return Err(platform_error(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Visual 0x{visual_id:x} does not use softbuffer's pixel format and is unsupported"
+                    ),
+                )
+                .into());
+            }
+        };
 
-        // See if SHM is available.
+        // See if SHM is available. Images are only put from our own connection once waiting for
+        // them needs it, and pixmaps are only presented once the surface asks for vsync or more
+        // buffers.
         let buffer = if display.is_shm_available {
             // SHM is available.
-            Buffer::Shm(ShmBuffer {
-                seg: None,
-                done_processing: None,
-            })
+            Buffer::Shm(ShmBuffer::new())
         } else {
             // SHM is not available.
             Buffer::Wire(Vec::new())
@@ -293,8 +604,15 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
             gc,
             depth: geometry_reply.depth,
             visual_id,
+            format,
+            alpha_mode: AlphaMode::default(),
             buffer,
+            events: None,
             buffer_presented: false,
+            present_event: None,
+            frame_requested: false,
+            present_serial: 0,
+            frame_pending: None,
             size: None,
             window_handle: window_src,
         })
@@ -305,6 +623,29 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
         &self.window_handle
     }
 
+    fn capabilities(&self) -> Capabilities {
+        let capabilities = Capabilities {
+            fetch: true,
+            // Without SHM, the whole image is sent over the wire.
+            partial_damage: !matches!(self.buffer, Buffer::Wire(_)),
+            present_modes: if self.is_ring_available() {
+                vec![PresentMode::Vsync, PresentMode::Immediate]
+            } else {
+                vec![PresentMode::Immediate]
+            },
+            ..Capabilities::new()
+        };
+        match &self.buffer {
+            Buffer::Present(ring) => Capabilities {
+                max_buffer_age: ring.count.try_into().unwrap_or(u8::MAX),
+                frame_pacing: ring.options == present::Option::NONE,
+                ..capabilities
+            },
+            _ => capabilities,
+        }
+        .with_max_size(u16::MAX.into())
+    }
+
     fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
         log::trace!(
             "resize: window={:X}, size={}x{}",
@@ -323,9 +664,15 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
         }))?;
 
         if self.size != Some((width, height)) {
+            let len =
+                total_len(width.get(), height.get()).ok_or(SoftBufferError::SizeOutOfRange {
+                    width: width.into(),
+                    height: height.into(),
+                })?;
+
             self.buffer_presented = false;
             self.buffer
-                .resize(self.display.connection(), width.get(), height.get())
+                .resize(self.display.connection(), len)
                 .swbuf_err("Failed to resize X11 buffer")?;
 
             // We successfully resized the buffer.
@@ -341,6 +688,20 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
         // Finish waiting on the previous `shm::PutImage` request, if any.
         self.buffer.finish_wait(self.display.connection())?;
 
+        if let Buffer::Present(ring) = &mut self.buffer {
+            // Wait for the server to stop reading from one of the pixmaps.
+            ring.wait_for_free(None)?;
+            if let Some((width, height)) = self.size {
+                ring.acquire(self.window, width.get(), height.get(), self.depth)?;
+            }
+        }
+
+        // The server is done with the buffer, so hand it back with the alpha it was left with.
+        if let Some((width, _)) = self.size {
+            // SAFETY: We waited for the server above.
+            unsafe { self.buffer.clear_alpha(width.get().into()) };
+        }
+
         // We can now safely call `buffer_mut` on the buffer.
         Ok(BufferImpl(self))
     }
@@ -348,9 +709,11 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
     fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
         log::trace!("fetch: window={:X}", self.window);
 
-        let (width, height) = self
-            .size
-            .expect("Must set size of surface before calling `fetch()`");
+        let (width, height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
+
+        // The last image may have been put from our own connection, which isn't ordered with this
+        // one.
+        self.buffer.finish_wait(self.display.connection())?;
 
         // TODO: Is it worth it to do SHM here? Probably not.
         let reply = self
@@ -374,12 +737,206 @@ impl<D: HasDisplayHandle + ?Sized, W: HasWindowHandle> SurfaceInterface<D, W> fo
             bytemuck::cast_slice_mut::<u32, u8>(&mut out).copy_from_slice(&reply.data);
             Ok(out)
         } else {
-            Err(SoftBufferError::PlatformError(
-                Some("Mismatch between reply and window data".into()),
-                None,
+            Err(platform_error(
+                ErrorKind::ProtocolError,
+                "Mismatch between reply and window data",
             ))
         }
     }
+
+    fn supported_formats(&self) -> &[PixelFormat] {
+        // The format is decided by the window's visual, which can't be changed.
+        std::slice::from_ref(&self.format)
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(SoftBufferError::Unimplemented)
+        }
+    }
+
+    fn set_alpha_mode(&mut self, mode: AlphaMode) -> Result<(), SoftBufferError> {
+        // Only 32-bit visuals have room for an alpha channel.
+        if mode == AlphaMode::Premultiplied && self.depth != 32 {
+            return Err(SoftBufferError::Unimplemented);
+        }
+
+        self.alpha_mode = mode;
+        Ok(())
+    }
+
+    fn set_buffer_count(&mut self, count: NonZeroUsize) -> Result<(), SoftBufferError> {
+        if count.get() == 1 && !matches!(self.buffer, Buffer::Present(_)) {
+            return Ok(());
+        }
+
+        self.start_ring()?;
+        if let Buffer::Present(ring) = &mut self.buffer {
+            ring.count = count.get();
+            ring.clear();
+        }
+        Ok(())
+    }
+
+    fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), SoftBufferError> {
+        // Only presented pixmaps wait for vertical blank. Otherwise, images are drawn to the window
+        // as soon as the server gets to them.
+        if mode == PresentMode::Vsync {
+            self.start_ring()?;
+        }
+        if let Buffer::Present(ring) = &mut self.buffer {
+            ring.options = match mode {
+                PresentMode::Vsync => present::Option::NONE,
+                PresentMode::Immediate => present::Option::ASYNC,
+            };
+        }
+        Ok(())
+    }
+
+    fn wait_for_buffer(&mut self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+        // Only `ShmCompletion` events can be waited for with a timeout.
+        self.use_events()?;
+        match &mut self.buffer {
+            Buffer::Shm(shm) => shm.wait_for_completion(timeout),
+            Buffer::Present(ring) => ring.wait_for_free(timeout),
+            Buffer::Wire(_) => Ok(true),
+        }
+    }
+
+    fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.display.readiness_fd()
+    }
+
+    fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        self.frame_requested = true;
+        Ok(())
+    }
+
+    fn frame_ready(&mut self) -> Result<bool, SoftBufferError> {
+        let (Some(serial), Some(eid)) = (self.frame_pending, self.present_event) else {
+            return Ok(true);
+        };
+        let Some(events) = &self.events else {
+            return Ok(true);
+        };
+
+        events.dispatch()?;
+        if events.state(eid, |state| state.completed) == Some(serial) {
+            self.frame_pending = None;
+        }
+        Ok(self.frame_pending.is_none())
+    }
+
+    fn last_frame_timing(&self) -> Option<FrameTiming> {
+        let eid = self.present_event?;
+        self.events.as_ref()?.state(eid, |state| state.timing)
+    }
+}
+
+impl<D: ?Sized, W: ?Sized> X11Impl<D, W> {
+    /// Whether the server takes the top byte as alpha, while the caller leaves it at zero.
+    fn needs_alpha(&self) -> bool {
+        self.depth == 32 && self.alpha_mode == AlphaMode::Opaque
+    }
+
+    /// Whether the surface can switch to presenting pixmaps.
+    fn is_ring_available(&self) -> bool {
+        self.display
+            .events()
+            .is_some_and(|events| events.is_pixmap_present_available)
+    }
+
+    /// Start using our own connection, opening it on first use.
+    ///
+    /// From then on, images are put from there too, so that the server tells us when it's done
+    /// with them.
+    fn use_events(&mut self) -> Result<Option<Arc<EventConnection>>, SoftBufferError> {
+        if self.events.is_none() {
+            self.events = self.display.events().cloned();
+            if let (Some(events), Buffer::Shm(shm)) = (&self.events, &mut self.buffer) {
+                if events.is_shm_available {
+                    shm.set_events(self.display.connection(), events.clone())
+                        .swbuf_err("Failed to move X11 buffer to our own connection")?;
+                }
+            }
+        }
+        Ok(self.events.clone())
+    }
+
+    /// Wait for the server to handle the requests that the application sent so far.
+    ///
+    /// Our own connection isn't ordered with the application's, which may have just resized or
+    /// mapped the window that a frame is drawn to.
+    fn sync(&self) -> Result<(), SoftBufferError> {
+        self.display
+            .connection()
+            .get_input_focus()
+            .swbuf_err("Failed to send X11 sync request")?
+            .reply()
+            .swbuf_err("Failed to sync X11 connection")?;
+        Ok(())
+    }
+
+    /// Switch to presenting pixmaps, if the surface doesn't already.
+    fn start_ring(&mut self) -> Result<(), SoftBufferError> {
+        if matches!(self.buffer, Buffer::Present(_)) {
+            return Ok(());
+        }
+
+        // The pixmaps replace the buffer, so it doesn't need to move to our own connection.
+        let events = match self.display.events() {
+            Some(events) if events.is_pixmap_present_available => events.clone(),
+            _ => return Err(SoftBufferError::Unimplemented),
+        };
+        self.events = Some(events.clone());
+        let eid = match self.present_event {
+            Some(eid) => eid,
+            None => *self
+                .present_event
+                .insert(events.select_present_events(self.window)?),
+        };
+
+        // The server may still be reading from the old buffer.
+        self.buffer.finish_wait(self.display.connection())?;
+        self.buffer = Buffer::Present(PixmapRing::new(events, eid));
+        self.buffer_presented = false;
+        if let Some((width, height)) = self.size {
+            let len =
+                total_len(width.get(), height.get()).ok_or(SoftBufferError::SizeOutOfRange {
+                    width: width.into(),
+                    height: height.into(),
+                })?;
+            self.buffer
+                .resize(self.display.connection(), len)
+                .swbuf_err("Failed to resize X11 buffer")?;
+        }
+        Ok(())
+    }
+
+    /// Ask the X server to tell us about the next vertical blank.
+    fn notify_next_frame(&mut self) -> Result<(), SoftBufferError> {
+        let Some(events) = self
+            .use_events()?
+            .filter(|events| events.is_present_available)
+        else {
+            return Ok(());
+        };
+        if self.present_event.is_none() {
+            self.present_event = Some(events.select_present_events(self.window)?);
+        }
+
+        // With a divisor of one, this completes at the vertical blank after the current one.
+        let conn = &events.connection;
+        self.present_serial = self.present_serial.wrapping_add(1);
+        conn.present_notify_msc(self.window, self.present_serial, 0, 1, 0)
+            .swbuf_err("Failed to send Present notify request")?;
+        conn.flush().swbuf_err("Failed to flush X11 connection")?;
+        self.frame_pending = Some(self.present_serial);
+
+        Ok(())
+    }
 }
 
 pub struct BufferImpl<'a, D: ?Sized, W: ?Sized>(&'a mut X11Impl<D, W>);
@@ -400,10 +957,28 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
     }
 
     fn age(&self) -> u8 {
-        if self.0.buffer_presented {
-            1
-        } else {
-            0
+        match &self.0.buffer {
+            Buffer::Present(ring) => ring.age(),
+            _ if self.0.buffer_presented => 1,
+            _ => 0,
+        }
+    }
+
+    fn copy_from_front(&mut self, rects: &[Rect]) -> bool {
+        let needs_alpha = self.0.needs_alpha();
+        match (&mut self.0.buffer, self.0.size) {
+            (Buffer::Present(ring), Some((width, _))) => {
+                let stride = width.get() as usize;
+                if !ring.copy_from_front(stride, rects) {
+                    return false;
+                }
+                if needs_alpha {
+                    // The front pixmap has the alpha that was set for the server.
+                    set_alpha(ring.pixels_mut(), stride, rects, 0);
+                }
+                true
+            }
+            _ => false,
         }
     }
 
@@ -411,17 +986,48 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
     fn present_with_damage(self, damage: &[Rect]) -> Result<(), SoftBufferError> {
         let imp = self.0;
 
-        let (surface_width, surface_height) = imp
-            .size
-            .expect("Must set size of surface before calling `present_with_damage()`");
+        let (surface_width, surface_height) = imp.size.ok_or(SoftBufferError::SizeNotSet)?;
 
         log::trace!("present: window={:X}", imp.window);
 
+        // A presented pixmap also updates the damage of the frames that weren't shown yet.
+        let damage = match imp.buffer {
+            Buffer::Present(ref mut ring) => {
+                imp.present_serial = imp.present_serial.wrapping_add(1);
+                Cow::Owned(ring.queue_damage(imp.present_serial, damage)?)
+            }
+            _ => Cow::Borrowed(damage),
+        };
+
+        // The server takes the top byte as alpha, which the caller leaves at zero.
+        let needs_alpha = imp.needs_alpha();
+        if needs_alpha {
+            // SAFETY: We called `finish_wait` on the buffer, so it is safe to call `buffer_mut`.
+            unsafe { imp.buffer.fill_alpha(surface_width.get().into(), &damage) };
+        }
+
+        // The requests of the frame may be sent from our own connection.
+        let is_from_events = match &imp.buffer {
+            Buffer::Shm(shm) => shm.events.is_some(),
+            Buffer::Wire(_) => false,
+            Buffer::Present(_) => true,
+        };
+        if is_from_events {
+            imp.sync()?;
+        }
+
         match imp.buffer {
             Buffer::Wire(ref wire) => {
                 // This is a suboptimal strategy, raise a stink in the debug logs.
                 log::debug!("Falling back to non-SHM method for window drawing.");
 
+                // The whole image is sent, so set the alpha on a copy of it.
+                let wire = if needs_alpha {
+                    Cow::Owned(wire.iter().map(|pixel| pixel | 0xff000000).collect())
+                } else {
+                    Cow::Borrowed(wire)
+                };
+
                 imp.display
                     .connection()
                     .put_image(
@@ -434,7 +1040,7 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
                         0,
                         0,
                         imp.depth,
-                        bytemuck::cast_slice(wire),
+                        bytemuck::cast_slice(&wire),
                     )
                     .map(|c| c.ignore_error())
                     .push_err()
@@ -446,9 +1052,15 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
                 // SAFETY: We know that we called finish_wait() before this.
                 // Put the image into the window.
                 if let Some((_, segment_id)) = shm.seg {
+                    let events = shm.events.clone();
+                    let conn = events
+                        .as_deref()
+                        .map_or(imp.display.connection(), |events| &events.connection);
+                    let mut completion = None;
                     damage
                         .iter()
-                        .try_for_each(|rect| {
+                        .enumerate()
+                        .try_for_each(|(i, rect)| {
                             let (src_x, src_y, dst_x, dst_y, width, height) = (|| {
                                 Some((
                                     u16::try_from(rect.x).ok()?,
@@ -461,9 +1073,8 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
                             })(
                             )
                             .ok_or(SoftBufferError::DamageOutOfRange { rect: *rect })?;
-                            imp.display
-                                .connection()
-                                .shm_put_image(
+                            let put = |conn: &XCBConnection, send_event| {
+                                conn.shm_put_image(
                                     imp.window,
                                     imp.gc,
                                     surface_width.get(),
@@ -476,33 +1087,64 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
                                     dst_y,
                                     imp.depth,
                                     xproto::ImageFormat::Z_PIXMAP.into(),
-                                    false,
+                                    send_event,
                                     segment_id,
                                     0,
                                 )
                                 .push_err()
-                                .map(|c| c.ignore_error())
                                 .swbuf_err("Failed to draw image to window")
+                                .map(|c| {
+                                    let sequence = c.sequence_number();
+                                    // Errors of the awaited request go to the event queue, where
+                                    // they end the wait.
+                                    if !send_event {
+                                        c.ignore_error();
+                                    }
+                                    sequence
+                                })
+                            };
+                            match events.as_deref() {
+                                // Only the last request needs to tell when it's done, since the
+                                // server handles them in order.
+                                Some(events) if i == damage.len() - 1 => {
+                                    completion = Some(events.send_awaited(|conn| put(conn, true))?);
+                                }
+                                _ => {
+                                    put(conn, false)?;
+                                }
+                            }
+                            Ok(())
                         })
                         .and_then(|()| {
+                            if damage.is_empty() {
+                                return Ok(());
+                            }
                             // Send a short request to act as a notification for when the X server is done processing the image.
-                            shm.begin_wait(imp.display.connection())
+                            shm.begin_wait(imp.display.connection(), completion)
                                 .swbuf_err("Failed to draw image to window")
                         })?;
                 }
             }
+
+            Buffer::Present(ref mut ring) => {
+                ring.present(imp.window, imp.present_serial, &damage)?;
+            }
         }
 
         imp.buffer_presented = true;
+        if mem::take(&mut imp.frame_requested) {
+            match imp.buffer {
+                // The pixmap's `CompleteNotify` tells when it was shown.
+                Buffer::Present(_) => imp.frame_pending = Some(imp.present_serial),
+                _ => imp.notify_next_frame()?,
+            }
+        }
 
         Ok(())
     }
 
     fn present(self) -> Result<(), SoftBufferError> {
-        let (width, height) = self
-            .0
-            .size
-            .expect("Must set size of surface before calling `present()`");
+        let (width, height) = self.0.size.ok_or(SoftBufferError::SizeNotSet)?;
         self.present_with_damage(&[Rect {
             x: 0,
             y: 0,
@@ -513,24 +1155,24 @@ impl<'a, D: HasDisplayHandle + ?Sized, W: HasWindowHandle + ?Sized> BufferInterf
 }
 
 impl Buffer {
-    /// Resize the buffer to the given size.
-    fn resize(
-        &mut self,
-        conn: &impl Connection,
-        width: u16,
-        height: u16,
-    ) -> Result<(), PushBufferError> {
+    /// Resize the buffer to the given length in bytes.
+    fn resize(&mut self, conn: &XCBConnection, len: usize) -> Result<(), PushBufferError> {
         match self {
-            Buffer::Shm(ref mut shm) => shm.alloc_segment(conn, total_len(width, height)),
+            Buffer::Shm(ref mut shm) => shm.alloc_segment(conn, len),
             Buffer::Wire(wire) => {
-                wire.resize(total_len(width, height) / 4, 0);
+                wire.resize(len / 4, 0);
+                Ok(())
+            }
+            Buffer::Present(ring) => {
+                ring.len = len;
+                ring.clear();
                 Ok(())
             }
         }
     }
 
     /// Finish waiting for an ongoing `shm::PutImage` request, if there is one.
-    fn finish_wait(&mut self, conn: &impl Connection) -> Result<(), SoftBufferError> {
+    fn finish_wait(&mut self, conn: &XCBConnection) -> Result<(), SoftBufferError> {
         if let Buffer::Shm(ref mut shm) = self {
             shm.finish_wait(conn)
                 .swbuf_err("Failed to wait for X11 buffer")?;
@@ -549,6 +1191,7 @@ impl Buffer {
         match self {
             Buffer::Shm(ref shm) => unsafe { shm.as_ref() },
             Buffer::Wire(wire) => wire,
+            Buffer::Present(ring) => ring.pixels(),
         }
     }
 
@@ -562,20 +1205,116 @@ impl Buffer {
         match self {
             Buffer::Shm(ref mut shm) => unsafe { shm.as_mut() },
             Buffer::Wire(wire) => wire,
+            Buffer::Present(ring) => ring.pixels_mut(),
+        }
+    }
+
+    /// The rectangles of the shared buffer whose alpha was made opaque for the server.
+    fn opaque_rects(&mut self) -> Option<&mut Vec<Rect>> {
+        match self {
+            Buffer::Shm(shm) => Some(&mut shm.opaque_rects),
+            Buffer::Wire(_) => None,
+            Buffer::Present(ring) => ring
+                .current
+                .map(|current| &mut ring.pixmaps[current].opaque_rects),
+        }
+    }
+
+    /// Make the alpha of the pixels in `damage` opaque, if the buffer is shared with the server.
+    ///
+    /// # Safety
+    ///
+    /// `finish_wait()` must be called in between `shm::PutImage` requests and this function.
+    unsafe fn fill_alpha(&mut self, stride: usize, damage: &[Rect]) {
+        let Some(rects) = self.opaque_rects() else {
+            return;
+        };
+        rects.extend_from_slice(damage);
+        set_alpha(unsafe { self.buffer_mut() }, stride, damage, 0xff);
+    }
+
+    /// Undo `fill_alpha`, once the server is done with the buffer.
+    ///
+    /// # Safety
+    ///
+    /// `finish_wait()` must be called in between `shm::PutImage` requests and this function.
+    unsafe fn clear_alpha(&mut self, stride: usize) {
+        let Some(rects) = self.opaque_rects().map(mem::take) else {
+            return;
+        };
+        set_alpha(unsafe { self.buffer_mut() }, stride, &rects, 0);
+    }
+}
+
+/// Convert `rect` to an X11 rectangle, if it fits.
+fn rectangle(rect: &Rect) -> Option<xproto::Rectangle> {
+    Some(xproto::Rectangle {
+        x: i16::try_from(rect.x).ok()?,
+        y: i16::try_from(rect.y).ok()?,
+        width: u16::try_from(rect.width.get()).ok()?,
+        height: u16::try_from(rect.height.get()).ok()?,
+    })
+}
+
+/// Add the damage of the presented frame `serial` to the frames in `unshown`, and get all of it.
+///
+/// At most `count` frames are kept.
+fn queue_unshown(
+    unshown: &mut Vec<(u32, Vec<Rect>)>,
+    count: usize,
+    serial: u32,
+    damage: &[Rect],
+) -> Vec<Rect> {
+    unshown.push((serial, damage.to_vec()));
+    if unshown.len() > count {
+        // Frames that are never shown, like while the window is hidden, mustn't pile up. The
+        // next frame keeps the damage until it is shown itself.
+        let (_, rects) = unshown.remove(0);
+        let next = &mut unshown[0].1;
+        next.extend(rects);
+        *next = region::simplify_damage(next).into_owned();
+    }
+    unshown
+        .iter()
+        .flat_map(|(_, rects)| rects.iter().copied())
+        .collect()
+}
+
+/// Set the top byte of the pixels in `rects` to `alpha`.
+fn set_alpha(pixels: &mut [u32], stride: usize, rects: &[Rect], alpha: u8) {
+    for rect in rects {
+        let (x, width) = (rect.x as usize, rect.width.get() as usize);
+        for y in rect.y as usize..(rect.y + rect.height.get()) as usize {
+            for pixel in &mut pixels[y * stride + x..y * stride + x + width] {
+                *pixel = (*pixel & 0x00ffffff) | u32::from(alpha) << 24;
+            }
         }
     }
 }
 
 impl ShmBuffer {
+    fn new() -> Self {
+        Self {
+            seg: None,
+            events: None,
+            opaque_rects: Vec::new(),
+            completion: None,
+            done_processing: None,
+        }
+    }
+
     /// Allocate a new `ShmSegment` of the given size.
     fn alloc_segment(
         &mut self,
-        conn: &impl Connection,
+        conn: &XCBConnection,
         buffer_size: usize,
     ) -> Result<(), PushBufferError> {
         // Round the size up to the next power of two to prevent frequent reallocations.
         let size = buffer_size.next_power_of_two();
 
+        // The contents are undefined after resizing.
+        self.opaque_rects.clear();
+
         // Get the size of the segment currently in use.
         let needs_realloc = match self.seg {
             Some((ref seg, _)) => seg.size() < size,
@@ -635,15 +1374,33 @@ impl ShmBuffer {
         }
     }
 
-    /// Associate an SHM segment with the server.
-    fn associate(
+    /// Put images from our own connection `events` from now on, instead of from `conn`.
+    fn set_events(
         &mut self,
-        conn: &impl Connection,
-        seg: ShmSegment,
+        conn: &XCBConnection,
+        events: Arc<EventConnection>,
     ) -> Result<(), PushBufferError> {
+        self.finish_wait(conn)?;
+        let seg = self.seg.take();
+        if let Some((_, id)) = seg {
+            conn.shm_detach(id)?.ignore_error();
+        }
+
+        self.events = Some(events);
+        if let Some((seg, _)) = seg {
+            self.associate(conn, seg)?;
+        }
+        Ok(())
+    }
+
+    /// Associate an SHM segment with the server.
+    fn associate(&mut self, conn: &XCBConnection, seg: ShmSegment) -> Result<(), PushBufferError> {
+        let events = self.events.clone();
+        let conn = events.as_deref().map_or(conn, |events| &events.connection);
+
         // Register the guard.
         let new_id = conn.generate_id()?;
-        conn.shm_attach_fd(new_id, seg.as_fd().try_clone_to_owned().unwrap(), true)?
+        conn.shm_attach_fd(new_id, seg.as_fd().try_clone_to_owned()?, true)?
             .ignore_error();
 
         // Take out the old one and detach it.
@@ -661,15 +1418,59 @@ impl ShmBuffer {
     }
 
     /// Begin waiting for the SHM processing to finish.
-    fn begin_wait(&mut self, c: &impl Connection) -> Result<(), PushBufferError> {
+    ///
+    /// `completion` is the `shm::PutImage` request that asked for a `ShmCompletion` event, if any.
+    fn begin_wait(
+        &mut self,
+        c: &impl Connection,
+        completion: Option<SequenceNumber>,
+    ) -> Result<(), PushBufferError> {
+        if let (Some(events), Some(completion)) = (&self.events, completion) {
+            events.connection.flush()?;
+            let old_completion = self.completion.replace(completion);
+            debug_assert!(old_completion.is_none());
+            return Ok(());
+        }
+
         let cookie = c.get_input_focus()?.sequence_number();
         let old_cookie = self.done_processing.replace(cookie);
         debug_assert!(old_cookie.is_none());
         Ok(())
     }
 
+    /// Wait for the `ShmCompletion` event, for at most `timeout` if it is set.
+    ///
+    /// Returns `false` if the timeout ran out first. Without our own connection, this leaves the
+    /// waiting to `finish_wait`.
+    fn wait_for_completion(&mut self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+        let (Some(completion), Some(events)) = (self.completion, &self.events) else {
+            return Ok(true);
+        };
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            events.dispatch()?;
+            if events.take_finished(completion) {
+                self.completion = None;
+                return Ok(true);
+            }
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if !events.wait(timeout)? {
+                return Ok(false);
+            }
+        }
+    }
+
     /// Wait for the SHM processing to finish.
     fn finish_wait(&mut self, c: &impl Connection) -> Result<(), PushBufferError> {
+        if let (Some(completion), Some(events)) = (self.completion.take(), &self.events) {
+            while !events.take_finished(completion) {
+                events.connection.flush()?;
+                let (event, sequence) = events.connection.wait_for_event_with_sequence()?;
+                events.handle(event, sequence);
+            }
+        }
+
         if let Some(done_processing) = self.done_processing.take() {
             // Cast to a cookie and wait on it.
             let cookie = Cookie::<_, xproto::GetInputFocusReply>::new(c, done_processing);
@@ -680,6 +1481,338 @@ impl ShmBuffer {
     }
 }
 
+/// The pixmaps that are presented in turn, so that one can be drawn to while the server reads
+/// from the others.
+struct PixmapRing {
+    /// The connection that owns the pixmaps, and gets their Present events.
+    events: Arc<EventConnection>,
+
+    /// The Present event context of the window.
+    eid: u32,
+
+    /// The pixmaps, which are created as they are needed.
+    pixmaps: Vec<RingPixmap>,
+
+    /// The most pixmaps to create.
+    count: usize,
+
+    /// The length of the pixmaps in bytes.
+    len: usize,
+
+    /// The pixmap that is being drawn to.
+    current: Option<usize>,
+
+    /// The number of pixmaps presented so far.
+    presents: u64,
+
+    /// The options of the `PresentPixmap` requests.
+    options: present::Option,
+
+    /// The serials and damage of the presented frames that weren't shown yet.
+    ///
+    /// The server only copies the update region of a pixmap to the window, and skips frames that
+    /// are replaced before they are shown. The damage of those is updated along with the next one.
+    unshown: Vec<(u32, Vec<Rect>)>,
+}
+
+struct RingPixmap {
+    /// The shared memory segment, paired with its ID.
+    seg: (ShmSegment, shm::Seg),
+
+    /// The pixmap using the segment.
+    pixmap: xproto::Pixmap,
+
+    /// The server may read from the pixmap, until it sends `IdleNotify`.
+    busy: bool,
+
+    /// The value of `presents` when the pixmap was last presented.
+    presented: Option<u64>,
+
+    /// The rectangles whose alpha was made opaque for the server.
+    opaque_rects: Vec<Rect>,
+}
+
+impl PixmapRing {
+    fn new(events: Arc<EventConnection>, eid: u32) -> Self {
+        Self {
+            events,
+            eid,
+            pixmaps: Vec::new(),
+            count: 3,
+            len: 0,
+            current: None,
+            presents: 0,
+            options: present::Option::NONE,
+            unshown: Vec::new(),
+        }
+    }
+
+    /// Handle the events that arrived, and mark the pixmaps the server is done with.
+    fn dispatch(&mut self) -> Result<(), SoftBufferError> {
+        self.events.dispatch()?;
+        let (idle, shown) = self
+            .events
+            .state(self.eid, |state| (mem::take(&mut state.idle), state.shown));
+        for pixmap in &mut self.pixmaps {
+            if idle.contains(&pixmap.pixmap) {
+                pixmap.busy = false;
+            }
+        }
+
+        // A frame that was shown updated the damage of the frames before it too.
+        if let Some(shown) = shown {
+            self.unshown
+                .retain(|&(serial, _)| (serial.wrapping_sub(shown) as i32) > 0);
+        }
+        Ok(())
+    }
+
+    /// Whether `acquire` has a pixmap to use, without waiting.
+    fn has_free(&self) -> bool {
+        self.current.is_some()
+            || self.pixmaps.len() < self.count
+            || self.pixmaps.iter().any(|pixmap| !pixmap.busy)
+    }
+
+    /// Wait until a pixmap is free, for at most `timeout` if it is set.
+    ///
+    /// Returns `false` if the timeout ran out first.
+    fn wait_for_free(&mut self, timeout: Option<Duration>) -> Result<bool, SoftBufferError> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            self.dispatch()?;
+            if self.has_free() {
+                return Ok(true);
+            }
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if !self.events.wait(timeout)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Pick the pixmap to draw to, creating it if needed.
+    ///
+    /// `wait_for_free` must have returned `true` before this.
+    fn acquire(
+        &mut self,
+        window: xproto::Window,
+        width: u16,
+        height: u16,
+        depth: u8,
+    ) -> Result<(), SoftBufferError> {
+        if self.current.is_some() {
+            return Ok(());
+        }
+
+        // The pixmap that was presented last has the least to redraw.
+        let free = self
+            .pixmaps
+            .iter()
+            .enumerate()
+            .filter(|(_, pixmap)| !pixmap.busy)
+            .max_by_key(|(_, pixmap)| pixmap.presented)
+            .map(|(index, _)| index);
+        let index = match free {
+            Some(index) => index,
+            None => {
+                let conn = &self.events.connection;
+                let seg = ShmSegment::new(self.len, self.len)
+                    .swbuf_err("Failed to create shared memory segment")?;
+                let seg_id = conn.generate_id().swbuf_err("Failed to generate SHM ID")?;
+                conn.shm_attach_fd(
+                    seg_id,
+                    seg.as_fd()
+                        .try_clone_to_owned()
+                        .swbuf_err("Failed to duplicate SHM file descriptor")?,
+                    true,
+                )
+                .swbuf_err("Failed to send SHM attach request")?
+                .ignore_error();
+
+                let pixmap = conn
+                    .generate_id()
+                    .swbuf_err("Failed to generate pixmap ID")?;
+                let created = conn
+                    .shm_create_pixmap(pixmap, window, width, height, depth, seg_id, 0)
+                    .swbuf_err("Failed to send pixmap creation request")?
+                    .check();
+                let pixmap = RingPixmap {
+                    seg: (seg, seg_id),
+                    pixmap,
+                    busy: false,
+                    presented: None,
+                    opaque_rects: Vec::new(),
+                };
+                if let Err(err) = created {
+                    pixmap.free(conn);
+                    return Err(err).swbuf_err("Failed to create pixmap");
+                }
+
+                self.pixmaps.push(pixmap);
+                self.pixmaps.len() - 1
+            }
+        };
+
+        self.current = Some(index);
+        Ok(())
+    }
+
+    /// Show the current pixmap in `window`, updating `damage`.
+    /// Keep the damage of the frame `serial` until it is shown, and get the damage that
+    /// presenting the current pixmap updates.
+    fn queue_damage(&mut self, serial: u32, damage: &[Rect]) -> Result<Vec<Rect>, SoftBufferError> {
+        // Damage that doesn't fit into a request mustn't be kept for the next frames either.
+        if let Some(rect) = damage.iter().find(|rect| rectangle(rect).is_none()) {
+            return Err(SoftBufferError::DamageOutOfRange { rect: *rect });
+        }
+
+        if self.current.is_none() {
+            return Ok(damage.to_vec());
+        }
+        Ok(queue_unshown(&mut self.unshown, self.count, serial, damage))
+    }
+
+    /// Present the current pixmap, updating `update` from `queue_damage`.
+    fn present(
+        &mut self,
+        window: xproto::Window,
+        serial: u32,
+        update: &[Rect],
+    ) -> Result<(), SoftBufferError> {
+        let Some(current) = self.current.take() else {
+            return Ok(());
+        };
+        let pixmap = &mut self.pixmaps[current];
+        let rectangles = update.iter().filter_map(rectangle).collect::<Vec<_>>();
+
+        let conn = &self.events.connection;
+        let update = conn
+            .generate_id()
+            .swbuf_err("Failed to generate region ID")?;
+        conn.xfixes_create_region(update, &rectangles)
+            .swbuf_err("Failed to send region creation request")?
+            .ignore_error();
+        conn.present_pixmap(
+            window,
+            pixmap.pixmap,
+            serial,
+            x11rb::NONE,
+            update,
+            0,
+            0,
+            x11rb::NONE,
+            x11rb::NONE,
+            x11rb::NONE,
+            self.options.into(),
+            0,
+            0,
+            0,
+            &[],
+        )
+        .swbuf_err("Failed to send Present request")?;
+        conn.xfixes_destroy_region(update)
+            .swbuf_err("Failed to send region destruction request")?
+            .ignore_error();
+        conn.flush().swbuf_err("Failed to flush X11 connection")?;
+
+        self.presents += 1;
+        pixmap.busy = true;
+        pixmap.presented = Some(self.presents);
+        Ok(())
+    }
+
+    /// The age of the current pixmap.
+    fn age(&self) -> u8 {
+        let presented = self
+            .current
+            .and_then(|current| self.pixmaps[current].presented);
+        match presented {
+            Some(presented) => u8::try_from(self.presents - presented + 1).unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Copy `rects` of the pixmap that was presented last into the current one.
+    fn copy_from_front(&mut self, stride: usize, rects: &[Rect]) -> bool {
+        let (Some(current), Some(front)) = (
+            self.current,
+            self.pixmaps
+                .iter()
+                .position(|pixmap| pixmap.presented == Some(self.presents)),
+        ) else {
+            return false;
+        };
+        if front == current {
+            return true;
+        }
+
+        let (front, current) = if front < current {
+            let (left, right) = self.pixmaps.split_at_mut(current);
+            (&left[front], &mut right[0])
+        } else {
+            let (left, right) = self.pixmaps.split_at_mut(front);
+            (&right[0], &mut left[current])
+        };
+        // SAFETY: The server only reads from the front pixmap, and is done with the current one.
+        let (src, dst) = unsafe { (front.seg.0.as_ref(), current.seg.0.as_mut()) };
+        util::copy_rects(
+            bytemuck::cast_slice(src),
+            bytemuck::cast_slice_mut(dst),
+            stride,
+            rects,
+        );
+        true
+    }
+
+    fn pixels(&self) -> &[u32] {
+        match self.current {
+            // SAFETY: The server is done with the current pixmap.
+            Some(current) => bytemuck::cast_slice(unsafe { self.pixmaps[current].seg.0.as_ref() }),
+            None => &[],
+        }
+    }
+
+    fn pixels_mut(&mut self) -> &mut [u32] {
+        match self.current {
+            // SAFETY: The server is done with the current pixmap.
+            Some(current) => {
+                bytemuck::cast_slice_mut(unsafe { self.pixmaps[current].seg.0.as_mut() })
+            }
+            None => &mut [],
+        }
+    }
+
+    /// Free the pixmaps, after the size or count changed.
+    fn clear(&mut self) {
+        self.current = None;
+        self.unshown.clear();
+        for pixmap in self.pixmaps.drain(..) {
+            pixmap.free(&self.events.connection);
+        }
+        let _ = self.events.connection.flush();
+    }
+}
+
+impl RingPixmap {
+    fn free(self, conn: &impl Connection) {
+        // The server keeps the memory until it is done with the pixmap.
+        if let Ok(token) = conn.free_pixmap(self.pixmap) {
+            token.ignore_error();
+        }
+        if let Ok(token) = conn.shm_detach(self.seg.1) {
+            token.ignore_error();
+        }
+    }
+}
+
+impl Drop for PixmapRing {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
 struct ShmSegment {
     id: File,
     ptr: NonNull<i8>,
@@ -796,9 +1929,14 @@ impl<D: ?Sized, W: ?Sized> Drop for X11Impl<D, W> {
             shm.finish_wait(self.display.connection()).ok();
 
             if let Some((segment, seg_id)) = shm.seg.take() {
-                if let Ok(token) = self.display.connection().shm_detach(seg_id) {
+                let conn = shm
+                    .events
+                    .as_deref()
+                    .map_or(self.display.connection(), |events| &events.connection);
+                if let Ok(token) = conn.shm_detach(seg_id) {
                     token.ignore_error();
                 }
+                let _ = conn.flush();
 
                 // Drop the segment.
                 drop(segment);
@@ -809,9 +1947,31 @@ impl<D: ?Sized, W: ?Sized> Drop for X11Impl<D, W> {
         if let Ok(token) = self.display.connection().free_gc(self.gc) {
             token.ignore_error();
         }
+
+        // Stop the events for the window.
+        if let (Some(eid), Some(events)) = (self.present_event, &self.events) {
+            events.deselect_present_events(eid, self.window);
+        }
     }
 }
 
+/// Check that `connection` reaches the same server as the application's connection `app`, by
+/// looking up a pixmap that `app` creates by its ID.
+fn is_same_server(app: &XCBConnection, connection: &XCBConnection) -> Result<bool, ReplyOrIdError> {
+    let Some(screen) = app.setup().roots.first() else {
+        return Ok(false);
+    };
+
+    // Wait for the pixmap to be created, since the connections aren't ordered with each other.
+    let pixmap = app.generate_id()?;
+    app.create_pixmap(1, pixmap, screen.root, 1, 1)?.check()?;
+    let geometry = connection.get_geometry(pixmap)?.reply();
+    app.free_pixmap(pixmap)?.ignore_error();
+    app.flush()?;
+
+    Ok(geometry.is_ok_and(|geometry| geometry.root == screen.root && geometry.depth == 1))
+}
+
 /// Create a shared memory identifier.
 fn create_shm_id() -> io::Result<OwnedFd> {
     use posix_shm::{Mode, ShmOFlags};
@@ -865,8 +2025,13 @@ fn is_shm_available(c: &impl Connection) -> bool {
         Err(_) => return false,
     };
 
+    let fd = match seg.as_fd().try_clone_to_owned() {
+        Ok(fd) => fd,
+        Err(_) => return false,
+    };
+
     let (attach, detach) = {
-        let attach = c.shm_attach_fd(seg_id, seg.as_fd().try_clone_to_owned().unwrap(), false);
+        let attach = c.shm_attach_fd(seg_id, fd, false);
         let detach = c.shm_detach(seg_id);
 
         match (attach, detach) {
@@ -879,17 +2044,19 @@ fn is_shm_available(c: &impl Connection) -> bool {
     matches!((attach.check(), detach.check()), (Ok(()), Ok(())))
 }
 
-/// Collect all visuals that use softbuffer's pixel format
-fn supported_visuals(c: &impl Connection) -> HashSet<Visualid> {
-    // Check that depth 24 uses 32 bits per pixels
-    if !c
+/// Collect all visuals that use one of softbuffer's pixel formats
+fn supported_visuals(c: &impl Connection) -> HashMap<Visualid, PixelFormat> {
+    // Check which of depth 24, depth 30 (10 bits per channel) and depth 32 (with alpha) use 32
+    // bits per pixels
+    let depths = c
         .setup()
         .pixmap_formats
         .iter()
-        .any(|f| f.depth == 24 && f.bits_per_pixel == 32)
-    {
+        .filter(|f| matches!(f.depth, 24 | 30 | 32) && f.bits_per_pixel == 32)
+        .map(|f| f.depth)
+        .collect::<Vec<_>>();
+    if !depths.contains(&24) {
         log::warn!("X11 server does not have a depth 24 format with 32 bits per pixel");
-        return HashSet::new();
     }
 
     // How does the server represent red, green, blue components of a pixel?
@@ -897,11 +2064,24 @@ fn supported_visuals(c: &impl Connection) -> HashSet<Visualid> {
     let own_byte_order = ImageOrder::LSB_FIRST;
     #[cfg(target_endian = "big")]
     let own_byte_order = ImageOrder::MSB_FIRST;
-    let expected_masks = if c.setup().image_byte_order == own_byte_order {
-        (0xff0000, 0xff00, 0xff)
-    } else {
-        // This is the byte-swapped version of our wished-for format
-        (0xff00, 0xff0000, 0xff000000)
+    let same_byte_order = c.setup().image_byte_order == own_byte_order;
+    let format_of_masks = |masks: (u32, u32, u32)| {
+        let (red, green, blue) = if same_byte_order {
+            masks
+        } else {
+            // Undo the byte-swap to get the masks in our byte order
+            (
+                masks.0.swap_bytes(),
+                masks.1.swap_bytes(),
+                masks.2.swap_bytes(),
+            )
+        };
+        match (red, green, blue) {
+            (0xff0000, 0xff00, 0xff) => Some(PixelFormat::Xrgb8888),
+            (0xff, 0xff00, 0xff0000) => Some(PixelFormat::Xbgr8888),
+            (0x3ff00000, 0xffc00, 0x3ff) => Some(PixelFormat::Xrgb2101010),
+            _ => None,
+        }
     };
 
     c.setup()
@@ -911,7 +2091,7 @@ fn supported_visuals(c: &impl Connection) -> HashSet<Visualid> {
             screen
                 .allowed_depths
                 .iter()
-                .filter(|depth| depth.depth == 24)
+                .filter(|depth| depths.contains(&depth.depth))
                 .flat_map(|depth| {
                     depth
                         .visuals
@@ -921,11 +2101,15 @@ fn supported_visuals(c: &impl Connection) -> HashSet<Visualid> {
                             visual.class == VisualClass::TRUE_COLOR
                                 || visual.class == VisualClass::DIRECT_COLOR
                         })
-                        .filter(|visual| {
-                            // Colors must be laid out as softbuffer expects
-                            expected_masks == (visual.red_mask, visual.green_mask, visual.blue_mask)
+                        .filter_map(|visual| {
+                            // Colors must be laid out as one of softbuffer's formats
+                            let format = format_of_masks((
+                                visual.red_mask,
+                                visual.green_mask,
+                                visual.blue_mask,
+                            ))?;
+                            Some((visual.visual_id, format))
                         })
-                        .map(|visual| visual.visual_id)
                 })
         })
         .collect()
@@ -996,13 +2180,53 @@ impl<T, E: Into<PushBufferError>> PushResultExt<T, E> for Result<T, E> {
 }
 
 /// Get the length that a slice needs to be to hold a buffer of the given dimensions.
+///
+/// Returns `None` if it doesn't fit into the address space.
 #[inline(always)]
-fn total_len(width: u16, height: u16) -> usize {
+fn total_len(width: u16, height: u16) -> Option<usize> {
     let width: usize = width.into();
     let height: usize = height.into();
 
-    width
-        .checked_mul(height)
-        .and_then(|len| len.checked_mul(4))
-        .unwrap_or_else(|| panic!("Dimensions are too large: ({} x {})", width, height))
+    width.checked_mul(height)?.checked_mul(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_len_checks_for_overflow() {
+        assert_eq!(total_len(3, 5), Some(60));
+
+        let max = u64::from(u16::MAX);
+        assert_eq!(
+            total_len(u16::MAX, u16::MAX),
+            usize::try_from(max * max * 4).ok()
+        );
+    }
+
+    #[test]
+    fn skipped_frames_stay_opaque() {
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        };
+        let mut unshown = Vec::new();
+        let mut pixels = vec![0x00123456; 4 * 4];
+
+        // The first frame is replaced by the second before it is shown, so the second one updates
+        // the left half too.
+        queue_unshown(&mut unshown, 2, 1, &[rect(0, 0, 2, 4)]);
+        let update = queue_unshown(&mut unshown, 2, 2, &[rect(2, 0, 2, 2)]);
+        set_alpha(&mut pixels, 4, &update, 0xff);
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let (x, y) = (i % 4, i / 4);
+            let opaque = x < 2 || y < 2;
+            assert_eq!(pixel >> 24 == 0xff, opaque, "alpha at ({x}, {y})");
+            assert_eq!(pixel & 0x00ffffff, 0x123456);
+        }
+    }
 }
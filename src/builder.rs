@@ -0,0 +1,162 @@
+//! Configuring surfaces when they are created.
+
+use crate::backend_interface::SurfaceInterface;
+use crate::{AlphaMode, Context, PixelFormat, SoftBufferError, Surface};
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use std::num::{NonZeroU32, NonZeroUsize};
+
+/// When presented frames are shown.
+///
+/// Set with [`SurfaceBuilder::with_present_mode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PresentMode {
+    /// Frames are shown at the next vertical blank, or composited by the display server.
+    ///
+    /// This is what most backends do. X11 needs the Present extension for it, and switches from
+    /// drawing to the window to presenting pixmaps when it is asked for.
+    Vsync,
+
+    /// Frames are shown as soon as possible, which may cause tearing.
+    ///
+    /// This is supported on X11 (the default without the Present extension), on KMS if the driver
+    /// supports asynchronous page flips, and on the headless backend. Check
+    /// [`Capabilities::present_modes`](crate::Capabilities::present_modes).
+    Immediate,
+}
+
+/// Creates a [`Surface`] with options that are set before it is first used.
+///
+/// Options that the backend doesn't support make [`SurfaceBuilder::build`] fail with
+/// [`SoftBufferError::Unimplemented`], instead of being ignored.
+///
+/// ```no_run
+/// # fn build<D, W>(context: &softbuffer::Context<D>, window: W)
+/// # where
+/// #     D: raw_window_handle::HasDisplayHandle,
+/// #     W: raw_window_handle::HasWindowHandle,
+/// # {
+/// use softbuffer::{PixelFormat, SurfaceBuilder};
+/// use std::num::NonZeroU32;
+///
+/// let mut surface = SurfaceBuilder::new(window)
+///     .with_size(NonZeroU32::new(640).unwrap(), NonZeroU32::new(480).unwrap())
+///     .with_format(PixelFormat::Xbgr8888)
+///     .build(context)
+///     .unwrap();
+///
+/// // The size is already set, so a buffer can be taken right away.
+/// let buffer = surface.buffer_mut().unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SurfaceBuilder<W> {
+    window: W,
+    size: Option<(NonZeroU32, NonZeroU32)>,
+    format: Option<PixelFormat>,
+    alpha_mode: Option<AlphaMode>,
+    buffer_count: Option<NonZeroUsize>,
+    present_mode: Option<PresentMode>,
+}
+
+impl<W: HasWindowHandle> SurfaceBuilder<W> {
+    /// Start building a surface for `window`.
+    pub fn new(window: W) -> Self {
+        Self {
+            window,
+            size: None,
+            format: None,
+            alpha_mode: None,
+            buffer_count: None,
+            present_mode: None,
+        }
+    }
+
+    /// Set the initial size, like [`Surface::resize`].
+    pub fn with_size(mut self, width: NonZeroU32, height: NonZeroU32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Set the pixel format, like [`Surface::set_format`].
+    pub fn with_format(mut self, format: PixelFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Set the alpha mode, like [`Surface::set_alpha_mode`].
+    pub fn with_alpha_mode(mut self, mode: AlphaMode) -> Self {
+        self.alpha_mode = Some(mode);
+        self
+    }
+
+    /// Set the number of buffers the backend cycles through.
+    ///
+    /// Wayland, KMS and the headless backend use two buffers, and the other backends use one. Only
+    /// X11 with the Present extension supports other counts yet, by presenting pixmaps like with
+    /// [`PresentMode::Vsync`], where up to three are used by default.
+    pub fn with_buffer_count(mut self, count: NonZeroUsize) -> Self {
+        self.buffer_count = Some(count);
+        self
+    }
+
+    /// Set when presented frames are shown.
+    pub fn with_present_mode(mut self, mode: PresentMode) -> Self {
+        self.present_mode = Some(mode);
+        self
+    }
+
+    /// Create the surface.
+    pub fn build<D: HasDisplayHandle>(
+        self,
+        context: &Context<D>,
+    ) -> Result<Surface<D, W>, SoftBufferError> {
+        let mut surface = Surface::new(context, self.window)?;
+
+        if let Some(count) = self.buffer_count {
+            surface.surface_impl.set_buffer_count(count)?;
+        }
+        if let Some(mode) = self.present_mode {
+            surface.surface_impl.set_present_mode(mode)?;
+        }
+        if let Some(mode) = self.alpha_mode {
+            surface.set_alpha_mode(mode)?;
+        }
+        if let Some(format) = self.format {
+            surface.set_format(format)?;
+        }
+        if let Some((width, height)) = self.size {
+            surface.resize(width, height)?;
+        }
+
+        Ok(surface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NoWindowHandle, SoftBufferError};
+
+    #[test]
+    fn builder_configures_the_surface() {
+        let context = Context::headless();
+        let mut surface = SurfaceBuilder::new(NoWindowHandle(()))
+            .with_size(NonZeroU32::new(2).unwrap(), NonZeroU32::new(1).unwrap())
+            .with_format(PixelFormat::Xbgr8888)
+            .with_alpha_mode(AlphaMode::Premultiplied)
+            .with_present_mode(PresentMode::Immediate)
+            .build(&context)
+            .unwrap();
+
+        // No resize is needed.
+        surface.buffer_mut().unwrap().present().unwrap();
+        assert_eq!(surface.presented_frames()[0].format, PixelFormat::Xbgr8888);
+        assert_eq!(surface.alpha_mode(), AlphaMode::Premultiplied);
+
+        let result = SurfaceBuilder::new(NoWindowHandle(()))
+            .with_buffer_count(NonZeroUsize::new(3).unwrap())
+            .build(&context);
+        assert!(matches!(result, Err(SoftBufferError::Unimplemented)));
+    }
+}
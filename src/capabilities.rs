@@ -0,0 +1,87 @@
+//! What a surface supports.
+
+use crate::{PixelFormat, PresentMode};
+use std::num::NonZeroU32;
+
+/// What a [`Surface`](crate::Surface) supports, returned by
+/// [`Surface::capabilities`](crate::Surface::capabilities).
+///
+/// This allows choosing a rendering strategy up front, instead of finding out through errors.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Whether [`Surface::fetch`](crate::Surface::fetch) is implemented.
+    pub fetch: bool,
+
+    /// Whether only the damage passed to
+    /// [`Buffer::present_with_damage`](crate::Buffer::present_with_damage) is uploaded.
+    ///
+    /// If this is `false`, the whole frame is always uploaded.
+    pub partial_damage: bool,
+
+    /// The largest size that can be passed to [`Surface::resize`](crate::Surface::resize).
+    pub max_size: (NonZeroU32, NonZeroU32),
+
+    /// The formats that are presented without converting them, best first.
+    pub formats: Vec<PixelFormat>,
+
+    /// The oldest [`Buffer::age`](crate::Buffer::age) a buffer can have, other than `0`.
+    ///
+    /// This is `1` for backends with a single buffer, `2` for double buffering, and `0` if the
+    /// contents of buffers are never kept.
+    pub max_buffer_age: u8,
+
+    /// Whether presenting is paced to the refresh rate of the display.
+    pub frame_pacing: bool,
+
+    /// The modes that can be passed to
+    /// [`SurfaceBuilder::with_present_mode`](crate::SurfaceBuilder::with_present_mode).
+    pub present_modes: Vec<PresentMode>,
+}
+
+impl Capabilities {
+    /// The capabilities of a backend that supports nothing optional, with a single buffer.
+    pub(crate) fn new() -> Self {
+        Self {
+            fetch: false,
+            partial_damage: false,
+            max_size: (NonZeroU32::MAX, NonZeroU32::MAX),
+            formats: Vec::new(),
+            max_buffer_age: 1,
+            frame_pacing: false,
+            present_modes: vec![PresentMode::Vsync],
+        }
+    }
+
+    /// Limit the size to what fits into `max` in each dimension.
+    #[cfg(any(x11_platform, wayland_platform, target_os = "windows"))]
+    pub(crate) fn with_max_size(self, max: u32) -> Self {
+        let max = NonZeroU32::new(max).expect("the maximum size is never zero");
+        Self {
+            max_size: (max, max),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::test_surface;
+    use crate::{PixelFormat, PresentMode};
+
+    #[test]
+    fn capabilities_include_formats() {
+        let surface = test_surface(1, 1);
+
+        let capabilities = surface.capabilities();
+        assert!(capabilities.fetch && capabilities.partial_damage);
+        assert_eq!(capabilities.formats, PixelFormat::ALL);
+        assert_eq!(capabilities.max_buffer_age, 2);
+        // Nothing paces a headless surface, frames are ready right away.
+        assert!(!capabilities.frame_pacing);
+        assert_eq!(
+            capabilities.present_modes,
+            [PresentMode::Vsync, PresentMode::Immediate]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use raw_window_handle::{HandleError, RawDisplayHandle, RawWindowHandle};
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::num::NonZeroU32;
 
 #[derive(Debug)]
@@ -86,24 +87,75 @@ pub enum SoftBufferError {
         height: NonZeroU32,
     },
 
-    /// The provided damage rect is outside of the range supported by the backend.
+    /// The surface was used before its size was set with [`Surface::resize`].
+    ///
+    /// [`Surface::resize`]: crate::Surface::resize
+    SizeNotSet,
+
+    /// The provided damage rect is outside of the surface with [`DamagePolicy::Strict`], or
+    /// outside of the range supported by the backend.
+    ///
+    /// [`DamagePolicy::Strict`]: crate::DamagePolicy::Strict
     DamageOutOfRange {
         /// The damage rect that was out of range.
         rect: crate::Rect,
     },
 
+    /// No buffer became free before the timeout passed to [`Surface::buffer_mut_timeout`] ran out.
+    ///
+    /// [`Surface::buffer_mut_timeout`]: crate::Surface::buffer_mut_timeout
+    Timeout,
+
+    /// No buffer was free when [`Surface::try_buffer_mut`] was called.
+    ///
+    /// [`Surface::try_buffer_mut`]: crate::Surface::try_buffer_mut
+    WouldBlock,
+
     /// A platform-specific backend error occurred.
     ///
     /// The first field provides a human-readable description of the error. The second field
     /// provides the actual error that occurred. Note that the second field is, under the hood,
     /// a private wrapper around the actual error, preventing the user from downcasting to the
-    /// actual error type.
+    /// actual error type. Use [`SoftBufferError::kind`] to find out what went wrong instead.
     PlatformError(Option<String>, Option<Box<dyn Error>>),
 
     /// This function is unimplemented on this platform.
     Unimplemented,
 }
 
+impl SoftBufferError {
+    /// The category of this error.
+    ///
+    /// For [`SoftBufferError::PlatformError`], this is worked out from the underlying error, and
+    /// is [`ErrorKind::Other`] if it doesn't fit any category.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::RawWindowHandle(HandleError::NotSupported) => ErrorKind::Unsupported,
+            Self::RawWindowHandle(HandleError::Unavailable) => ErrorKind::SurfaceLost,
+            Self::RawWindowHandle(_) => ErrorKind::Other,
+            Self::UnsupportedDisplayPlatform { .. }
+            | Self::UnsupportedWindowPlatform { .. }
+            | Self::Unimplemented => ErrorKind::Unsupported,
+            Self::IncompleteWindowHandle
+            | Self::IncompleteDisplayHandle
+            | Self::SizeOutOfRange { .. }
+            | Self::SizeNotSet
+            | Self::DamageOutOfRange { .. } => ErrorKind::InvalidInput,
+            Self::Timeout => ErrorKind::Timeout,
+            Self::WouldBlock => ErrorKind::WouldBlock,
+            Self::PlatformError(_, Some(err)) => classify(&**err),
+            Self::PlatformError(_, None) => ErrorKind::Other,
+        }
+    }
+
+    /// Whether the operation that failed may succeed if it is tried again.
+    ///
+    /// This is a shortcut for `self.kind().is_retryable()`.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
 impl fmt::Display for SoftBufferError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -131,6 +183,12 @@ impl fmt::Display for SoftBufferError {
                 f,
                 "Surface size {width}x{height} out of range for backend.",
             ),
+            Self::SizeNotSet => write!(
+                f,
+                "The surface size has not been set, call `Surface::resize()` first."
+            ),
+            Self::Timeout => write!(f, "Timed out waiting for a free buffer."),
+            Self::WouldBlock => write!(f, "No buffer is free."),
             Self::PlatformError(msg, None) => write!(f, "Platform error: {msg:?}"),
             Self::PlatformError(msg, Some(err)) => write!(f, "Platform error: {msg:?}: {err}"),
             Self::DamageOutOfRange { rect } => write!(
@@ -159,6 +217,202 @@ impl From<HandleError> for SoftBufferError {
     }
 }
 
+/// A category of [`SoftBufferError`], returned by [`SoftBufferError::kind`].
+///
+/// Each kind is either retryable or fatal, see [`ErrorKind::is_retryable`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The system or the display server ran out of memory.
+    ///
+    /// This is retryable, since memory may have been freed in the meantime.
+    OutOfMemory,
+
+    /// The connection to the display server was lost.
+    ///
+    /// This is fatal, the [`Context`](crate::Context) and all of its surfaces have to be
+    /// recreated.
+    ConnectionLost,
+
+    /// The window or the device it is shown on went away.
+    ///
+    /// This is fatal, the [`Surface`](crate::Surface) has to be recreated.
+    SurfaceLost,
+
+    /// The operation isn't allowed, for example because the process isn't the DRM master.
+    ///
+    /// This is fatal.
+    PermissionDenied,
+
+    /// The display server rejected a request or sent something that couldn't be understood.
+    ///
+    /// This is fatal.
+    ProtocolError,
+
+    /// The operation didn't complete in time.
+    ///
+    /// This is retryable.
+    Timeout,
+
+    /// The operation would have to wait, but was asked not to.
+    ///
+    /// This is retryable.
+    WouldBlock,
+
+    /// The operation isn't supported by the platform or the backend.
+    ///
+    /// This is fatal.
+    Unsupported,
+
+    /// An argument was invalid, such as a size or damage that is out of range.
+    ///
+    /// This is fatal, the same call will fail again.
+    InvalidInput,
+
+    /// An error that doesn't fit any other kind.
+    ///
+    /// This is treated as fatal.
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether an operation that failed with this kind of error may succeed if it is tried again.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::OutOfMemory | Self::Timeout | Self::WouldBlock)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::OutOfMemory => "out of memory",
+            Self::ConnectionLost => "connection lost",
+            Self::SurfaceLost => "surface lost",
+            Self::PermissionDenied => "permission denied",
+            Self::ProtocolError => "protocol error",
+            Self::Timeout => "timed out",
+            Self::WouldBlock => "would block",
+            Self::Unsupported => "unsupported",
+            Self::InvalidInput => "invalid input",
+            Self::Other => "other error",
+        })
+    }
+}
+
+/// Work out the kind of a platform error from its source chain.
+fn classify(err: &(dyn Error + 'static)) -> ErrorKind {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(err) = err.downcast_ref::<LibraryError>() {
+            return err.kind;
+        }
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return classify_io(err);
+        }
+        #[cfg(any(x11_platform, wayland_platform, kms_platform))]
+        if let Some(&errno) = err.downcast_ref::<rustix::io::Errno>() {
+            return classify_io(&errno.into());
+        }
+        #[cfg(x11_platform)]
+        if let Some(kind) = classify_x11(err) {
+            return kind;
+        }
+        #[cfg(wayland_platform)]
+        if let Some(kind) = classify_wayland(err) {
+            return kind;
+        }
+        current = err.source();
+    }
+    ErrorKind::Other
+}
+
+fn classify_io(err: &io::Error) -> ErrorKind {
+    #[cfg(any(x11_platform, wayland_platform, kms_platform))]
+    if err.raw_os_error() == Some(rustix::io::Errno::NODEV.raw_os_error()) {
+        return ErrorKind::SurfaceLost;
+    }
+
+    match err.kind() {
+        io::ErrorKind::OutOfMemory => ErrorKind::OutOfMemory,
+        io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+        io::ErrorKind::TimedOut => ErrorKind::Timeout,
+        io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::UnexpectedEof => ErrorKind::ConnectionLost,
+        io::ErrorKind::InvalidData => ErrorKind::ProtocolError,
+        io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+        io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+        _ => ErrorKind::Other,
+    }
+}
+
+#[cfg(x11_platform)]
+fn classify_x11(err: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    use x11rb::errors::{ConnectionError, ReplyError, ReplyOrIdError};
+    use x11rb::x11_utils::X11Error;
+
+    // libxcb shuts the connection down on any error, so most of them mean it is lost.
+    fn connection(err: &ConnectionError) -> ErrorKind {
+        match err {
+            ConnectionError::InsufficientMemory => ErrorKind::OutOfMemory,
+            ConnectionError::UnsupportedExtension => ErrorKind::Unsupported,
+            _ => ErrorKind::ConnectionLost,
+        }
+    }
+
+    fn x11(err: &X11Error) -> ErrorKind {
+        use x11rb::protocol::ErrorKind as X11ErrorKind;
+
+        match err.error_kind {
+            X11ErrorKind::Window | X11ErrorKind::Drawable | X11ErrorKind::Pixmap => {
+                ErrorKind::SurfaceLost
+            }
+            X11ErrorKind::Alloc => ErrorKind::OutOfMemory,
+            X11ErrorKind::Access => ErrorKind::PermissionDenied,
+            X11ErrorKind::Implementation => ErrorKind::Unsupported,
+            _ => ErrorKind::ProtocolError,
+        }
+    }
+
+    if let Some(err) = err.downcast_ref::<ConnectionError>() {
+        return Some(connection(err));
+    }
+    if let Some(err) = err.downcast_ref::<ReplyError>() {
+        return Some(match err {
+            ReplyError::ConnectionError(err) => connection(err),
+            ReplyError::X11Error(err) => x11(err),
+        });
+    }
+    err.downcast_ref::<ReplyOrIdError>().map(|err| match err {
+        ReplyOrIdError::IdsExhausted => ErrorKind::OutOfMemory,
+        ReplyOrIdError::ConnectionError(err) => connection(err),
+        ReplyOrIdError::X11Error(err) => x11(err),
+    })
+}
+
+#[cfg(wayland_platform)]
+fn classify_wayland(err: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    use wayland_client::backend::WaylandError;
+    use wayland_client::DispatchError;
+
+    let err = match err.downcast_ref::<DispatchError>() {
+        Some(DispatchError::BadMessage { .. }) => return Some(ErrorKind::ProtocolError),
+        Some(DispatchError::Backend(err)) => err,
+        None => err.downcast_ref::<WaylandError>()?,
+    };
+    Some(match err {
+        // The connection is closed once an error has been read from it.
+        WaylandError::Io(err) => match classify_io(err) {
+            ErrorKind::Other => ErrorKind::ConnectionLost,
+            kind => kind,
+        },
+        WaylandError::Protocol(_) => ErrorKind::ProtocolError,
+    })
+}
+
 /// Simple unit error type used to bubble up rejected platforms.
 pub(crate) enum InitError<D> {
     /// Failed to initialize.
@@ -184,12 +438,22 @@ impl<T> From<HandleError> for InitError<T> {
 #[allow(dead_code)]
 pub(crate) trait SwResultExt<T> {
     fn swbuf_err(self, msg: impl Into<String>) -> Result<T, SoftBufferError>;
+
+    /// Like `swbuf_err`, but with a kind that is known up front.
+    fn swbuf_err_kind(self, kind: ErrorKind, msg: impl Into<String>) -> Result<T, SoftBufferError>;
 }
 
 impl<T, E: std::error::Error + 'static> SwResultExt<T> for Result<T, E> {
     fn swbuf_err(self, msg: impl Into<String>) -> Result<T, SoftBufferError> {
         self.map_err(|e| {
-            SoftBufferError::PlatformError(Some(msg.into()), Some(Box::new(LibraryError(e))))
+            let kind = classify(&e);
+            SoftBufferError::PlatformError(Some(msg.into()), Some(LibraryError::boxed(kind, e)))
+        })
+    }
+
+    fn swbuf_err_kind(self, kind: ErrorKind, msg: impl Into<String>) -> Result<T, SoftBufferError> {
+        self.map_err(|e| {
+            SoftBufferError::PlatformError(Some(msg.into()), Some(LibraryError::boxed(kind, e)))
         })
     }
 }
@@ -198,24 +462,82 @@ impl<T> SwResultExt<T> for Option<T> {
     fn swbuf_err(self, msg: impl Into<String>) -> Result<T, SoftBufferError> {
         self.ok_or_else(|| SoftBufferError::PlatformError(Some(msg.into()), None))
     }
+
+    fn swbuf_err_kind(self, kind: ErrorKind, msg: impl Into<String>) -> Result<T, SoftBufferError> {
+        self.ok_or_else(|| platform_error(kind, msg))
+    }
+}
+
+/// Create a platform error that has no underlying error, but a known kind.
+pub(crate) fn platform_error(kind: ErrorKind, msg: impl Into<String>) -> SoftBufferError {
+    let error = LibraryError::boxed(kind, kind.to_string());
+    SoftBufferError::PlatformError(Some(msg.into()), Some(error))
 }
 
 /// A wrapper around a library error.
 ///
 /// This prevents `x11-dl` and `x11rb` from becoming public dependencies, since users cannot downcast
-/// to this type.
-struct LibraryError<E>(E);
+/// to this type. It also remembers the kind of the error, since that can't be worked out once the
+/// error is hidden.
+struct LibraryError {
+    kind: ErrorKind,
+    error: Box<dyn Error>,
+}
+
+impl LibraryError {
+    fn boxed(kind: ErrorKind, error: impl Into<Box<dyn Error>>) -> Box<dyn Error> {
+        Box::new(Self {
+            kind,
+            error: error.into(),
+        })
+    }
+}
 
-impl<E: fmt::Debug> fmt::Debug for LibraryError<E> {
+impl fmt::Debug for LibraryError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&self.0, f)
+        fmt::Debug::fmt(&self.error, f)
     }
 }
 
-impl<E: fmt::Display> fmt::Display for LibraryError<E> {
+impl fmt::Display for LibraryError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(&self.0, f)
+        fmt::Display::fmt(&self.error, f)
     }
 }
 
-impl<E: fmt::Debug + fmt::Display> std::error::Error for LibraryError<E> {}
+impl std::error::Error for LibraryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_errors_are_classified() {
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::BrokenPipe))
+            .swbuf_err("failed to write")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionLost);
+        assert!(!err.is_retryable());
+
+        let err = None::<()>
+            .swbuf_err_kind(ErrorKind::Timeout, "no buffer")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert!(err.is_retryable());
+
+        let err = SoftBufferError::PlatformError(
+            Some("out of memory".into()),
+            Some(io::Error::from(io::ErrorKind::OutOfMemory).into()),
+        );
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+
+        let err = SoftBufferError::PlatformError(Some("unknown".into()), None);
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(
+            SoftBufferError::Unimplemented.kind(),
+            ErrorKind::Unsupported
+        );
+        assert!(SoftBufferError::Timeout.is_retryable());
+        assert!(SoftBufferError::WouldBlock.is_retryable());
+    }
+}
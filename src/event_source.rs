@@ -0,0 +1,88 @@
+//! Integration of a [`Context`] into a [`calloop`] event loop.
+
+use crate::backend_dispatch::ContextDispatch;
+use crate::backend_interface::ContextInterface;
+use crate::{Context, SoftBufferError};
+
+use calloop::generic::Generic;
+use calloop::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+use raw_window_handle::HasDisplayHandle;
+use std::io;
+use std::os::fd::OwnedFd;
+
+impl<D: HasDisplayHandle> Context<D> {
+    /// Create a [`calloop`] event source that handles the events of this context.
+    ///
+    /// The source emits an event after handling the events that arrived on
+    /// [`Context::readiness_fd`], like with [`Context::dispatch_pending`]. A buffer may have been
+    /// released or a frame may be done, so [`Surface::try_buffer_mut`](crate::Surface::try_buffer_mut)
+    /// and [`Surface::frame_ready`](crate::Surface::frame_ready) can be tried again.
+    ///
+    /// Fails with [`SoftBufferError::Unimplemented`] if the backend has no file descriptor to wait
+    /// on.
+    pub fn event_source(&self) -> Result<ContextSource<D>, SoftBufferError> {
+        let (Some(context), Some(fd)) = (self.context_impl.try_clone(), self.readiness_fd()) else {
+            return Err(SoftBufferError::Unimplemented);
+        };
+        let fd = fd
+            .try_clone_to_owned()
+            .map_err(|err| SoftBufferError::PlatformError(None, Some(err.into())))?;
+
+        Ok(ContextSource {
+            context,
+            source: Generic::new(fd, Interest::READ, Mode::Level),
+        })
+    }
+}
+
+/// A [`calloop`] event source for the events of a [`Context`], see [`Context::event_source`].
+pub struct ContextSource<D> {
+    context: ContextDispatch<D>,
+    source: Generic<OwnedFd>,
+}
+
+impl<D: HasDisplayHandle> EventSource for ContextSource<D> {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+    type Error = io::Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let context = &self.context;
+        self.source.process_events(readiness, token, |_, _| {
+            context
+                .dispatch_pending()
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+            callback((), &mut ());
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.source.unregister(poll)
+    }
+}
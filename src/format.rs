@@ -0,0 +1,470 @@
+//! Pixel formats and the software conversion between them.
+
+use crate::Rect;
+use std::num::NonZeroU32;
+
+/// The layout of the pixels in a [`Buffer`](crate::Buffer).
+///
+/// Every pixel is one `u32`, and the name lists the channels from the most significant bit to
+/// the least significant bit, followed by the number of bits of each channel. `X` marks bits
+/// that are ignored.
+///
+/// Use [`Surface::supported_formats`](crate::Surface::supported_formats) to find the formats a
+/// surface can present without any conversion.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PixelFormat {
+    /// `00000000RRRRRRRRGGGGGGGGBBBBBBBB`
+    ///
+    /// This is the default format, and the only one every backend supports natively.
+    #[default]
+    Xrgb8888,
+
+    /// `00000000BBBBBBBBGGGGGGGGRRRRRRRR`
+    Xbgr8888,
+
+    /// `00RRRRRRRRRRGGGGGGGGGGBBBBBBBBBB`
+    ///
+    /// Surfaces that can't present 10 bits per channel dither this down to 8 bits.
+    Xrgb2101010,
+
+    /// `0000000000000000RRRRRGGGGGGBBBBB`
+    ///
+    /// The pixels still take up a whole `u32`, with the upper 16 bits ignored. This has no
+    /// alpha channel. Surfaces that present it natively, like KMS planes that only support 16-bit
+    /// formats, pack the pixels into 16 bits when they are presented.
+    Rgb565,
+}
+
+/// How the bits marked `X` in a [`PixelFormat`] are treated.
+///
+/// Set with [`Surface::set_alpha_mode`](crate::Surface::set_alpha_mode).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AlphaMode {
+    /// The `X` bits are ignored, and the window is opaque.
+    #[default]
+    Opaque,
+
+    /// The `X` bits are an alpha channel, and the color channels are already multiplied by it.
+    ///
+    /// Pixels with an alpha of `0` are fully transparent, so a buffer of all zeroes shows
+    /// nothing at all.
+    Premultiplied,
+}
+
+/// A 4x4 Bayer matrix, for ordered dithering.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// The threshold that rounds to the nearest value, out of 32.
+const ROUND: u32 = 16;
+
+impl PixelFormat {
+    /// Every format, for backends that support them all.
+    pub(crate) const ALL: &'static [PixelFormat] = &[
+        PixelFormat::Xrgb8888,
+        PixelFormat::Xbgr8888,
+        PixelFormat::Xrgb2101010,
+        PixelFormat::Rgb565,
+    ];
+
+    /// The number of bits in the smallest color channel.
+    fn channel_bits(self) -> u32 {
+        match self {
+            Self::Xrgb8888 | Self::Xbgr8888 => 8,
+            Self::Xrgb2101010 => 10,
+            Self::Rgb565 => 5,
+        }
+    }
+
+    /// Unpack a pixel into its red, green, blue and alpha channels, scaled to 16 bits.
+    ///
+    /// The alpha channel is made up of the `X` bits, which are only meaningful with
+    /// [`AlphaMode::Premultiplied`], but are carried along either way.
+    #[inline]
+    fn unpack(self, pixel: u32) -> [u16; 4] {
+        match self {
+            Self::Xrgb8888 => [pixel >> 16, pixel >> 8, pixel, pixel >> 24].map(|c| expand(c, 8)),
+            Self::Xbgr8888 => [pixel, pixel >> 8, pixel >> 16, pixel >> 24].map(|c| expand(c, 8)),
+            Self::Xrgb2101010 => {
+                let [r, g, b] = [pixel >> 20, pixel >> 10, pixel].map(|c| expand(c, 10));
+                [r, g, b, expand(pixel >> 30, 2)]
+            }
+            Self::Rgb565 => [
+                expand(pixel >> 11, 5),
+                expand(pixel >> 5, 6),
+                expand(pixel, 5),
+                // There are no `X` bits, which are zero in the other formats.
+                0,
+            ],
+        }
+    }
+
+    /// Pack 16 bit red, green, blue and alpha channels into a pixel.
+    ///
+    /// The color channels are rounded up if their remainder is above `threshold`, out of 32.
+    #[inline]
+    fn pack(self, [r, g, b, a]: [u16; 4], threshold: u32) -> u32 {
+        match self {
+            Self::Xrgb8888 | Self::Xbgr8888 => {
+                let [r, g, b] = [r, g, b].map(|c| quantize(c, 8, threshold));
+                let a = quantize(a, 8, ROUND);
+                if self == Self::Xrgb8888 {
+                    (a << 24) | (r << 16) | (g << 8) | b
+                } else {
+                    (a << 24) | (b << 16) | (g << 8) | r
+                }
+            }
+            Self::Xrgb2101010 => {
+                let [r, g, b] = [r, g, b].map(|c| quantize(c, 10, threshold));
+                (quantize(a, 2, ROUND) << 30) | (r << 20) | (g << 10) | b
+            }
+            Self::Rgb565 => {
+                (quantize(r, 5, threshold) << 11)
+                    | (quantize(g, 6, threshold) << 5)
+                    | quantize(b, 5, threshold)
+            }
+        }
+    }
+
+    /// Pick the format out of `supported` that `self` is best converted into.
+    pub(crate) fn closest(self, supported: &[PixelFormat]) -> Option<PixelFormat> {
+        if supported.contains(&self) {
+            Some(self)
+        } else {
+            supported.first().copied()
+        }
+    }
+}
+
+/// Scale the low `bits` bits of `channel` to 16 bits.
+#[inline]
+fn expand(channel: u32, bits: u32) -> u16 {
+    let max = (1 << bits) - 1;
+    ((channel & max) * 0xffff / max) as u16
+}
+
+/// Scale a 16 bit channel down to `bits` bits, rounding up if the remainder is above
+/// `threshold`, out of 32.
+#[inline]
+fn quantize(channel: u16, bits: u32, threshold: u32) -> u32 {
+    let max = (1u64 << bits) - 1;
+    let scaled = (u64::from(channel) * max * 32 + u64::from(threshold) * 0xffff) / (0xffff * 32);
+    scaled.min(max) as u32
+}
+
+/// Convert a row of pixels from one format into another.
+///
+/// If `dither` is set to the position of the first pixel, the pixels are dithered, so that
+/// converting to a format with fewer bits per channel doesn't cause banding.
+pub(crate) fn convert_row(
+    from: PixelFormat,
+    src: &[u32],
+    to: PixelFormat,
+    dst: &mut [u32],
+    dither: Option<(usize, usize)>,
+) {
+    debug_assert_eq!(src.len(), dst.len());
+
+    if from == to {
+        dst.copy_from_slice(src);
+        return;
+    }
+
+    match dither {
+        Some((x, y)) => {
+            let thresholds = &BAYER[y % 4];
+            for (i, (dst, &src)) in dst.iter_mut().zip(src).enumerate() {
+                let threshold = u32::from(thresholds[(x + i) % 4]) * 2 + 1;
+                *dst = to.pack(from.unpack(src), threshold);
+            }
+        }
+        None => {
+            for (dst, &src) in dst.iter_mut().zip(src) {
+                *dst = to.pack(from.unpack(src), ROUND);
+            }
+        }
+    }
+}
+
+/// A buffer in the caller's format, that is converted into the backend's format on present.
+pub(crate) struct ConversionBuffer {
+    /// The pixels, in the caller's format.
+    pixels: Vec<u32>,
+
+    /// The width of the buffer.
+    width: NonZeroU32,
+
+    /// The height of the buffer.
+    height: NonZeroU32,
+
+    /// Buffer has been presented.
+    presented: bool,
+}
+
+impl ConversionBuffer {
+    pub(crate) fn new(width: NonZeroU32, height: NonZeroU32) -> Self {
+        Self {
+            pixels: vec![0; width.get() as usize * height.get() as usize],
+            width,
+            height,
+            presented: false,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub(crate) fn pixels_mut(&mut self) -> &mut [u32] {
+        &mut self.pixels
+    }
+
+    pub(crate) fn age(&self) -> u8 {
+        if self.presented {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// A rect covering the whole buffer.
+    pub(crate) fn full_rect(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Convert the parts of the buffer covered by `damage` into `dst`, which has rows that are
+    /// `dst_stride` pixels apart.
+    ///
+    /// If `dither` is set, the pixels are dithered when `to` has fewer bits per channel.
+    pub(crate) fn convert_into(
+        &mut self,
+        from: PixelFormat,
+        to: PixelFormat,
+        dst: &mut [u32],
+        dst_stride: usize,
+        damage: &[Rect],
+        dither: bool,
+    ) {
+        let width = self.width.get() as usize;
+        let height = self.height.get() as usize;
+
+        for rect in damage {
+            // Damage outside of the buffer has nothing to convert.
+            let left = (rect.x as usize).min(width);
+            let right = (rect.x as usize)
+                .saturating_add(rect.width.get() as usize)
+                .min(width);
+            let top = (rect.y as usize).min(height);
+            let bottom = (rect.y as usize)
+                .saturating_add(rect.height.get() as usize)
+                .min(height);
+
+            // Dither only when precision is lost.
+            let dither = dither && to.channel_bits() < from.channel_bits();
+
+            for y in top..bottom {
+                let src_row = y * width;
+                let dst_row = y * dst_stride;
+                convert_row(
+                    from,
+                    &self.pixels[src_row + left..src_row + right],
+                    to,
+                    &mut dst[dst_row + left..dst_row + right],
+                    dither.then_some((left, y)),
+                );
+            }
+        }
+
+        self.presented = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_surface;
+
+    #[test]
+    fn swap_red_and_blue() {
+        let src = [0x00112233, 0x00ff0000];
+        let mut dst = [0; 2];
+        convert_row(
+            PixelFormat::Xrgb8888,
+            &src,
+            PixelFormat::Xbgr8888,
+            &mut dst,
+            None,
+        );
+        assert_eq!(dst, [0x00332211, 0x000000ff]);
+
+        let mut back = [0; 2];
+        convert_row(
+            PixelFormat::Xbgr8888,
+            &dst,
+            PixelFormat::Xrgb8888,
+            &mut back,
+            None,
+        );
+        assert_eq!(back, src);
+    }
+
+    #[test]
+    fn alpha_is_kept() {
+        let src = [0x80402010];
+        let mut dst = [0; 1];
+        convert_row(
+            PixelFormat::Xrgb8888,
+            &src,
+            PixelFormat::Xbgr8888,
+            &mut dst,
+            None,
+        );
+        assert_eq!(dst, [0x80102040]);
+    }
+
+    #[test]
+    fn ten_bit_round_trip() {
+        let src = [0x00123456, 0xffffffff, 0];
+        let mut deep = [0; 3];
+        convert_row(
+            PixelFormat::Xrgb8888,
+            &src,
+            PixelFormat::Xrgb2101010,
+            &mut deep,
+            None,
+        );
+        assert_eq!(deep[1], 0xffffffff);
+
+        let mut back = [0; 3];
+        convert_row(
+            PixelFormat::Xrgb2101010,
+            &deep,
+            PixelFormat::Xrgb8888,
+            &mut back,
+            None,
+        );
+        assert_eq!(back, src);
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        let gray = (0x200 << 20) | (0x200 << 10) | 0x200;
+        let src = [gray; 4];
+
+        let mut sum = 0;
+        for y in 0..4 {
+            let mut dst = [0; 4];
+            convert_row(
+                PixelFormat::Xrgb2101010,
+                &src,
+                PixelFormat::Xrgb8888,
+                &mut dst,
+                Some((0, y)),
+            );
+            sum += dst.iter().map(|pixel| pixel & 0xff).sum::<u32>();
+        }
+
+        // 512 / 1023 * 255 is about 127.62, and dithering averages 127.625 instead of
+        // rounding every pixel to 128.
+        assert_eq!(sum, 16 * 127 + 10);
+    }
+
+    #[test]
+    fn rgb565_uses_the_low_bits() {
+        let src = [0x00ff8000, 0x0000ff00];
+        let mut dst = [0; 2];
+        convert_row(
+            PixelFormat::Xrgb8888,
+            &src,
+            PixelFormat::Rgb565,
+            &mut dst,
+            None,
+        );
+        assert_eq!(dst, [0xfc00, 0x07e0]);
+
+        let mut back = [0; 2];
+        convert_row(
+            PixelFormat::Rgb565,
+            &dst,
+            PixelFormat::Xrgb8888,
+            &mut back,
+            None,
+        );
+        assert_eq!(back, [0x00ff8200, 0x0000ff00]);
+    }
+
+    #[test]
+    fn convert_only_damage() {
+        let (width, height) = (NonZeroU32::new(2).unwrap(), NonZeroU32::new(2).unwrap());
+        let mut buffer = ConversionBuffer::new(width, height);
+        buffer.pixels_mut().fill(0x00ff0000);
+
+        // Rows padded to three pixels.
+        let mut dst = [0; 6];
+        let damage = Rect {
+            x: 1,
+            y: 1,
+            width: NonZeroU32::new(5).unwrap(),
+            height: NonZeroU32::new(1).unwrap(),
+        };
+        assert_eq!(buffer.age(), 0);
+        buffer.convert_into(
+            PixelFormat::Xrgb8888,
+            PixelFormat::Xbgr8888,
+            &mut dst,
+            3,
+            &[damage],
+            true,
+        );
+        assert_eq!(dst, [0, 0, 0, 0, 0x000000ff, 0]);
+        assert_eq!(buffer.age(), 1);
+    }
+
+    #[test]
+    fn closest_prefers_exact_match() {
+        let supported = [PixelFormat::Xrgb8888, PixelFormat::Xbgr8888];
+        assert_eq!(
+            PixelFormat::Xbgr8888.closest(&supported),
+            Some(PixelFormat::Xbgr8888)
+        );
+        assert_eq!(
+            PixelFormat::Xbgr8888.closest(&[PixelFormat::Xrgb8888]),
+            Some(PixelFormat::Xrgb8888)
+        );
+    }
+
+    #[test]
+    fn format_is_recorded() {
+        let mut surface = test_surface(1, 1);
+        surface.set_format(PixelFormat::Xbgr8888).unwrap();
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer[0] = 0x00112233;
+        buffer.present().unwrap();
+
+        let frame = &surface.presented_frames()[0];
+        assert_eq!(frame.format, PixelFormat::Xbgr8888);
+        assert_eq!(frame.pixels, [0x00112233]);
+    }
+
+    #[test]
+    fn alpha_is_presented() {
+        let mut surface = test_surface(1, 1);
+        surface.set_alpha_mode(AlphaMode::Premultiplied).unwrap();
+        assert_eq!(surface.alpha_mode(), AlphaMode::Premultiplied);
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer[0] = 0x80400000;
+        buffer.present().unwrap();
+
+        assert_eq!(surface.presented_frames()[0].pixels, [0x80400000]);
+    }
+}
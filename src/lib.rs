@@ -10,23 +10,52 @@ use backend_dispatch::*;
 mod backend_interface;
 use backend_interface::*;
 mod backends;
+mod builder;
+mod capabilities;
 mod error;
+#[cfg(all(feature = "calloop", free_unix))]
+mod event_source;
+mod format;
+#[cfg(all(feature = "async", free_unix))]
+mod reactor;
+mod region;
+mod sub_buffer;
+mod timing;
 mod util;
 
 use std::cell::Cell;
+use std::fmt;
 use std::marker::PhantomData;
 use std::num::NonZeroU32;
 use std::ops;
+#[cfg(unix)]
+use std::os::fd::BorrowedFd;
 use std::sync::Arc;
+use std::time::Duration;
 
+pub use builder::{PresentMode, SurfaceBuilder};
+pub use capabilities::Capabilities;
 use error::InitError;
-pub use error::SoftBufferError;
+#[cfg(all(feature = "async", free_unix))]
+use error::SwResultExt;
+pub use error::{ErrorKind, SoftBufferError};
+#[cfg(all(feature = "calloop", free_unix))]
+pub use event_source::ContextSource;
+use format::ConversionBuffer;
+pub use format::{AlphaMode, PixelFormat};
+pub use region::{DamageHistory, DamagePolicy, Region};
+pub use sub_buffer::SubBuffer;
+pub use timing::FrameTiming;
 
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle};
 
+pub use backends::headless::PresentedFrame;
 #[cfg(target_arch = "wasm32")]
 pub use backends::web::SurfaceExtWeb;
 
+/// The number of frames of damage kept to preserve contents, enough for triple buffering.
+const PRESERVE_HISTORY: usize = 2;
+
 /// An instance of this struct contains the platform-specific data that must be managed in order to
 /// write to a window on that platform.
 pub struct Context<D> {
@@ -39,8 +68,80 @@ pub struct Context<D> {
 
 impl<D: HasDisplayHandle> Context<D> {
     /// Creates a new instance of this struct, using the provided display.
+    ///
+    /// The first backend that supports the display is used. Setting the `SOFTBUFFER_BACKEND`
+    /// environment variable to the name of a [`Backend`], like `x11`, only allows that backend,
+    /// and prefixing the name with `-`, like `-wayland`, skips that backend.
     pub fn new(display: D) -> Result<Self, SoftBufferError> {
-        match ContextDispatch::new(display) {
+        let env = std::env::var("SOFTBUFFER_BACKEND").ok().and_then(|value| {
+            let (skip, name) = match value.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, value.as_str()),
+            };
+            let backend = Backend::from_name(name);
+            if backend.is_none() {
+                log::warn!("Ignoring unknown backend {value:?} in SOFTBUFFER_BACKEND");
+            }
+            backend.map(|backend| (skip, backend))
+        });
+
+        Self::new_filtered(display, |backend| match env {
+            Some((false, forced)) => backend == forced,
+            Some((true, skipped)) => backend != skipped && backend != Backend::Headless,
+            None => backend != Backend::Headless,
+        })
+    }
+
+    /// Creates a new instance of this struct, using the provided display with `backend`.
+    ///
+    /// This fails if `backend` doesn't support the display, or isn't available on this platform.
+    /// Forcing [`Backend::Headless`] works with any display.
+    pub fn new_with_backend(display: D, backend: Backend) -> Result<Self, SoftBufferError> {
+        Self::new_filtered(display, |candidate| candidate == backend)
+    }
+
+    /// Creates a new instance of this struct, using the first backend that supports the provided
+    /// display, other than the `excluded` ones.
+    pub fn new_excluding_backends(
+        display: D,
+        excluded: &[Backend],
+    ) -> Result<Self, SoftBufferError> {
+        Self::new_filtered(display, |backend| {
+            backend != Backend::Headless && !excluded.contains(&backend)
+        })
+    }
+
+    /// Get the backend this context uses.
+    pub fn backend(&self) -> Backend {
+        self.context_impl.backend()
+    }
+
+    /// Get the file descriptor that becomes readable when the display has events for softbuffer,
+    /// like buffer releases and page flips.
+    ///
+    /// This is the Wayland connection, the DRM device, or on X11 a connection of softbuffer's own,
+    /// and it can be waited on by an event loop. Once it is readable, call
+    /// [`Context::dispatch_pending`].
+    ///
+    /// `None` if the backend has no file descriptor to wait on.
+    #[cfg(unix)]
+    pub fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.context_impl.readiness_fd()
+    }
+
+    /// Handle the events that arrived on [`Context::readiness_fd`], without blocking.
+    ///
+    /// Afterwards, [`Surface::try_buffer_mut`] succeeds if a buffer was released.
+    pub fn dispatch_pending(&self) -> Result<(), SoftBufferError> {
+        self.context_impl.dispatch_pending()
+    }
+
+    /// Create a context with the first backend that supports the display and is `allowed`.
+    fn new_filtered(
+        display: D,
+        allowed: impl Fn(Backend) -> bool,
+    ) -> Result<Self, SoftBufferError> {
+        match ContextDispatch::new_filtered(display, allowed) {
             Ok(context_impl) => Ok(Self {
                 context_impl,
                 _marker: PhantomData,
@@ -57,8 +158,94 @@ impl<D: HasDisplayHandle> Context<D> {
     }
 }
 
+impl Context<NoDisplayHandle> {
+    /// Creates a context that isn't connected to any display.
+    ///
+    /// Surfaces created from this context render into memory, and record every frame that is
+    /// presented to them. See [`Surface::new_headless`].
+    pub fn headless() -> Self {
+        Self {
+            context_impl: ContextDispatch::Headless(backends::headless::HeadlessDisplayImpl::new()),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A backend that a [`Context`] can use.
+///
+/// Which backends are available depends on the platform and the enabled features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    /// The X Window System, through XCB.
+    X11,
+
+    /// Wayland.
+    Wayland,
+
+    /// DRM/KMS, drawing directly to the screen.
+    Kms,
+
+    /// Windows.
+    Win32,
+
+    /// macOS.
+    CoreGraphics,
+
+    /// HTML canvases.
+    Web,
+
+    /// Redox.
+    Orbital,
+
+    /// Memory, without any display. See [`Context::headless`].
+    Headless,
+}
+
+impl Backend {
+    /// Every backend.
+    const ALL: &'static [Self] = &[
+        Self::X11,
+        Self::Wayland,
+        Self::Kms,
+        Self::Win32,
+        Self::CoreGraphics,
+        Self::Web,
+        Self::Orbital,
+        Self::Headless,
+    ];
+
+    /// Get the name of the backend, as used by the `SOFTBUFFER_BACKEND` environment variable.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::X11 => "x11",
+            Self::Wayland => "wayland",
+            Self::Kms => "kms",
+            Self::Win32 => "win32",
+            Self::CoreGraphics => "coregraphics",
+            Self::Web => "web",
+            Self::Orbital => "orbital",
+            Self::Headless => "headless",
+        }
+    }
+
+    /// Find a backend by its [name](Self::name), ignoring case.
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 /// A rectangular region of the buffer coordinate space.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Rect {
     /// x coordinate of top left corner
     pub x: u32,
@@ -74,17 +261,73 @@ pub struct Rect {
 pub struct Surface<D, W> {
     /// This is boxed so that `Surface` is the same size on every platform.
     surface_impl: Box<SurfaceDispatch<D, W>>,
+
+    /// The format the caller renders in.
+    format: PixelFormat,
+
+    /// The format the backend presents in.
+    native_format: PixelFormat,
+
+    /// How the `X` bits of the pixels are treated.
+    alpha_mode: AlphaMode,
+
+    /// Dither when converting to a format with fewer bits per channel.
+    dithering: bool,
+
+    /// The size set by the last successful resize.
+    size: Option<(NonZeroU32, NonZeroU32)>,
+
+    /// The buffer the caller renders into if `format` differs from `native_format`.
+    conversion: Option<ConversionBuffer>,
+
+    /// The damage of the last frames, if enabled.
+    damage_history: Option<DamageHistory>,
+
+    /// The damage of the last frames, kept to carry them forward if contents are preserved.
+    preserve_history: Option<DamageHistory>,
+
+    /// A copy of the last frame presented with [`Buffer::present_auto_damage`].
+    last_frame: Option<Vec<u32>>,
+
+    /// What to do with damage outside of the surface.
+    damage_policy: DamagePolicy,
+
     _marker: PhantomData<Cell<()>>,
 }
 
+impl<D: HasDisplayHandle, W: HasWindowHandle> Surface<D, W> {
+    /// Wrap a backend surface.
+    pub(crate) fn from_dispatch(surface_impl: SurfaceDispatch<D, W>) -> Self {
+        // Backends start out in their preferred format, which the default format is converted
+        // into if they can't present it.
+        let native_format = surface_impl
+            .supported_formats()
+            .first()
+            .copied()
+            .unwrap_or_default();
+
+        Self {
+            surface_impl: Box::new(surface_impl),
+            format: PixelFormat::default(),
+            native_format,
+            alpha_mode: AlphaMode::default(),
+            dithering: true,
+            size: None,
+            conversion: None,
+            damage_history: None,
+            preserve_history: None,
+            last_frame: None,
+            damage_policy: DamagePolicy::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<D: HasDisplayHandle, W: HasWindowHandle> Surface<D, W> {
     /// Creates a new surface for the context for the provided window.
     pub fn new(context: &Context<D>, window: W) -> Result<Self, SoftBufferError> {
         match SurfaceDispatch::new(window, &context.context_impl) {
-            Ok(surface_dispatch) => Ok(Self {
-                surface_impl: Box::new(surface_dispatch),
-                _marker: PhantomData,
-            }),
+            Ok(surface_dispatch) => Ok(Self::from_dispatch(surface_dispatch)),
             Err(InitError::Unsupported(window)) => {
                 let raw = window.window_handle()?.as_raw();
                 Err(SoftBufferError::UnsupportedWindowPlatform {
@@ -102,6 +345,29 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> Surface<D, W> {
         self.surface_impl.window()
     }
 
+    /// Get the frames presented to this surface so far, oldest first.
+    ///
+    /// Only surfaces created from a [`Context::headless`] record their frames. For every other
+    /// surface, this is empty.
+    pub fn presented_frames(&self) -> &[PresentedFrame] {
+        match &*self.surface_impl {
+            SurfaceDispatch::Headless(inner) => inner.presented_frames(),
+            #[allow(unreachable_patterns)]
+            _ => &[],
+        }
+    }
+
+    /// Take the frames presented to this surface so far, oldest first, and clear the record.
+    ///
+    /// See [`Surface::presented_frames`].
+    pub fn take_presented_frames(&mut self) -> Vec<PresentedFrame> {
+        match &mut *self.surface_impl {
+            SurfaceDispatch::Headless(inner) => inner.take_presented_frames(),
+            #[allow(unreachable_patterns)]
+            _ => Vec::new(),
+        }
+    }
+
     /// Set the size of the buffer that will be returned by [`Surface::buffer_mut`].
     ///
     /// If the size of the buffer does not match the size of the window, the buffer is drawn
@@ -109,7 +375,141 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> Surface<D, W> {
     /// to have the buffer fill the entire window. Use your windowing library to find the size
     /// of the window.
     pub fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) -> Result<(), SoftBufferError> {
-        self.surface_impl.resize(width, height)
+        self.surface_impl.resize(width, height)?;
+        if self.size != Some((width, height)) {
+            self.size = Some((width, height));
+            self.conversion = None;
+            self.last_frame = None;
+            for history in [&mut self.damage_history, &mut self.preserve_history]
+                .into_iter()
+                .flatten()
+            {
+                history.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Get what this surface supports.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            formats: self.supported_formats().to_vec(),
+            ..self.surface_impl.capabilities()
+        }
+    }
+
+    /// Get the format that the buffers returned by [`Surface::buffer_mut`] are in.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Get the formats this surface can present without converting them first, best first.
+    ///
+    /// Almost every surface supports [`PixelFormat::Xrgb8888`]. The exception are KMS planes
+    /// that only support 16-bit formats, which the default format is converted to.
+    pub fn supported_formats(&self) -> &[PixelFormat] {
+        self.surface_impl.supported_formats()
+    }
+
+    /// Set the format that the buffers returned by [`Surface::buffer_mut`] are in.
+    ///
+    /// If the format isn't one of the [`Surface::supported_formats`], the buffers are
+    /// converted into a supported format in software when they are presented. This costs an
+    /// extra copy of the presented pixels.
+    pub fn set_format(&mut self, format: PixelFormat) -> Result<(), SoftBufferError> {
+        let native_format = format
+            .closest(self.surface_impl.supported_formats())
+            .ok_or(SoftBufferError::Unimplemented)?;
+        self.surface_impl.set_format(native_format)?;
+
+        self.format = format;
+        self.native_format = native_format;
+        self.conversion = None;
+        Ok(())
+    }
+
+    /// Get how the `X` bits of the pixels are treated.
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    /// Set how the `X` bits of the pixels are treated.
+    ///
+    /// With [`AlphaMode::Premultiplied`], the window is composited with whatever is behind it.
+    /// This is supported on Wayland, on X11 windows created with a 32-bit ARGB visual, and on
+    /// the headless backend. Other backends return [`SoftBufferError::Unimplemented`].
+    pub fn set_alpha_mode(&mut self, mode: AlphaMode) -> Result<(), SoftBufferError> {
+        self.surface_impl.set_alpha_mode(mode)?;
+        self.alpha_mode = mode;
+        Ok(())
+    }
+
+    /// Get whether conversions to formats with fewer bits per channel are dithered.
+    pub fn dithering(&self) -> bool {
+        self.dithering
+    }
+
+    /// Set whether conversions to formats with fewer bits per channel are dithered.
+    ///
+    /// This only matters if the [`Surface::format`] has to be converted, for example
+    /// [`PixelFormat::Xrgb8888`] on a surface that only supports [`PixelFormat::Rgb565`].
+    /// Ordered dithering avoids visible banding in gradients, at a small cost in speed. It is
+    /// enabled by default.
+    pub fn set_dithering(&mut self, dithering: bool) {
+        self.dithering = dithering;
+    }
+
+    /// Get the damage history, if it is enabled.
+    pub fn damage_history(&self) -> Option<&DamageHistory> {
+        self.damage_history.as_ref()
+    }
+
+    /// Remember the damage of the last `frames` presented frames, or stop if `frames` is `0`.
+    ///
+    /// With the history enabled, [`Buffer::repair_region`] returns the part of each buffer that
+    /// is older than the last frame, so only that and the new damage need to be redrawn. A
+    /// history of one frame is enough for backends with two buffers; three or more buffers need
+    /// a longer history.
+    pub fn set_damage_history(&mut self, frames: usize) {
+        self.damage_history = match frames {
+            0 => None,
+            _ => Some(DamageHistory::new(frames)),
+        };
+    }
+
+    /// Get whether buffers are handed out holding the last presented frame.
+    pub fn preserve_contents(&self) -> bool {
+        self.preserve_history.is_some()
+    }
+
+    /// Set whether buffers are handed out holding the last presented frame.
+    ///
+    /// With this enabled, [`Surface::buffer_mut`] copies whatever changed since a buffer was
+    /// last presented from the buffer on screen, so only the parts of the frame that change need
+    /// to be drawn, without looking at [`Buffer::age`]. Buffers that hold the last frame then
+    /// have an age of `1`.
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// Backends with a single buffer keep its contents anyway. On macOS and on backends that
+    /// lost the last frame, for example after a resize, buffers keep an age of `0`.
+    pub fn set_preserve_contents(&mut self, preserve: bool) {
+        if preserve != self.preserve_contents() {
+            self.preserve_history = preserve.then(|| DamageHistory::new(PRESERVE_HISTORY));
+        }
+    }
+
+    /// Get what is done with damage that reaches outside of the surface.
+    pub fn damage_policy(&self) -> DamagePolicy {
+        self.damage_policy
+    }
+
+    /// Set what is done with damage that reaches outside of the surface.
+    ///
+    /// By default, damage is clipped to the surface. With [`DamagePolicy::Strict`],
+    /// [`Buffer::present_with_damage`] fails with [`SoftBufferError::DamageOutOfRange`] instead.
+    pub fn set_damage_policy(&mut self, policy: DamagePolicy) {
+        self.damage_policy = policy;
     }
 
     /// Copies the window contents into a buffer.
@@ -118,10 +518,16 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> Surface<D, W> {
     ///
     /// - On X11, the window must be visible.
     /// - On macOS, Redox and Wayland, this function is unimplemented.
+    /// - On headless surfaces, this returns the last presented frame.
     /// - On Web, this will fail if the content was supplied by
     ///   a different origin depending on the sites CORS rules.
     pub fn fetch(&mut self) -> Result<Vec<u32>, SoftBufferError> {
-        self.surface_impl.fetch()
+        let mut pixels = self.surface_impl.fetch()?;
+        if self.format != self.native_format {
+            let native = pixels.clone();
+            format::convert_row(self.native_format, &native, self.format, &mut pixels, None);
+        }
+        Ok(pixels)
     }
 
     /// Return a [`Buffer`] that the next frame should be rendered into. The size must
@@ -134,10 +540,174 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> Surface<D, W> {
     ///   `softbuffer`. Therefore it is the responsibility of the user to wait for the page flip before
     ///   sending another frame.
     pub fn buffer_mut(&mut self) -> Result<Buffer<'_, D, W>, SoftBufferError> {
-        Ok(Buffer {
-            buffer_impl: self.surface_impl.buffer_mut()?,
+        let buffer_impl = self.surface_impl.buffer_mut()?;
+
+        let (width, height) = self.size.ok_or(SoftBufferError::SizeNotSet)?;
+
+        let conversion = if self.format != self.native_format {
+            Some(Conversion {
+                buffer: self
+                    .conversion
+                    .get_or_insert_with(|| ConversionBuffer::new(width, height)),
+                from: self.format,
+                to: self.native_format,
+                dither: self.dithering,
+            })
+        } else {
+            None
+        };
+
+        let mut buffer = Buffer {
+            buffer_impl,
+            conversion,
+            width,
+            height,
+            damage_history: self.damage_history.as_mut(),
+            preserve_history: self.preserve_history.as_mut(),
+            repair_region: None,
+            preserved: false,
+            last_frame: &mut self.last_frame,
+            damage_policy: self.damage_policy,
             _marker: PhantomData,
-        })
+        };
+        if let Some(history) = &buffer.preserve_history {
+            let age = buffer.age();
+            if buffer.conversion.is_none() && age != 1 {
+                let repair = history.repair_region(age, width, height);
+                buffer.preserved = buffer.buffer_impl.copy_from_front(&repair);
+            }
+        }
+        if let Some(history) = &buffer.damage_history {
+            buffer.repair_region = Some(history.repair_region(buffer.age(), width, height));
+        }
+        Ok(buffer)
+    }
+
+    /// Like [`Surface::buffer_mut`], but fails with [`SoftBufferError::Timeout`] if no buffer
+    /// becomes free within `timeout`.
+    ///
+    /// A buffer is busy while the display server is still reading from it, which can take
+    /// arbitrarily long, for example while the window is minimized on Wayland.
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// - On X11, this opens a connection of softbuffer's own to the server in `DISPLAY` on first
+    ///   use. Until an upload was sent from there, or if it can't draw to the window, the round
+    ///   trip that waits for the server to finish the last upload can't be bounded, so this waits
+    ///   like [`Surface::buffer_mut`].
+    pub fn buffer_mut_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Buffer<'_, D, W>, SoftBufferError> {
+        if !self.surface_impl.wait_for_buffer(Some(timeout))? {
+            return Err(SoftBufferError::Timeout);
+        }
+        self.buffer_mut()
+    }
+
+    /// Like [`Surface::buffer_mut`], but fails with [`SoftBufferError::WouldBlock`] instead of
+    /// waiting if no buffer is free.
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// - On X11, if softbuffer's own connection can't draw to the window, this waits for the
+    ///   server like [`Surface::buffer_mut_timeout`].
+    pub fn try_buffer_mut(&mut self) -> Result<Buffer<'_, D, W>, SoftBufferError> {
+        if !self.surface_impl.wait_for_buffer(Some(Duration::ZERO))? {
+            return Err(SoftBufferError::WouldBlock);
+        }
+        self.buffer_mut()
+    }
+
+    /// Ask to be told when the display is ready for a new frame, after the next buffer is
+    /// presented.
+    ///
+    /// Once [`Surface::frame_ready`] returns `true`, drawing the next frame won't get ahead of the
+    /// display. This paces animations to the refresh rate instead of drawing as fast as possible.
+    /// See [`Buffer::present_and_request_frame`] to request a frame while holding a buffer.
+    pub fn request_frame(&mut self) -> Result<(), SoftBufferError> {
+        self.surface_impl.request_frame()
+    }
+
+    /// Check if the display is ready for the frame requested with [`Surface::request_frame`],
+    /// without blocking.
+    ///
+    /// This is `true` if no frame was requested. Wait for [`Surface::readiness_fd`] to find out
+    /// when to check again.
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// - On Wayland, this uses frame callbacks, which the compositor doesn't send while the
+    ///   window is hidden.
+    /// - On KMS, this is the page flip of the presented buffer.
+    /// - On X11, this is when the presented pixmap is shown, or the next vertical blank if the
+    ///   window is drawn to directly. Without the Present extension, frames are ready right away.
+    /// - On other platforms, frames are ready right away.
+    pub fn frame_ready(&mut self) -> Result<bool, SoftBufferError> {
+        self.surface_impl.frame_ready()
+    }
+
+    /// Get when the last presented frame was shown, if the display reported it.
+    ///
+    /// This is updated when the events of the display are handled, like by
+    /// [`Surface::frame_ready`] and [`Surface::buffer_mut`].
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// - On X11, this is reported by the Present extension for the presented pixmaps.
+    /// - On other platforms, this is always `None`.
+    pub fn last_frame_timing(&self) -> Option<FrameTiming> {
+        self.surface_impl.last_frame_timing()
+    }
+
+    /// Get the file descriptor that becomes readable when the display has events for this
+    /// surface, see [`Context::readiness_fd`].
+    ///
+    /// Once it is readable, [`Surface::try_buffer_mut`] handles the events itself.
+    #[cfg(unix)]
+    pub fn readiness_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.surface_impl.readiness_fd()
+    }
+
+    /// Like [`Surface::buffer_mut`], but resolves once a buffer is free instead of blocking.
+    ///
+    /// This doesn't depend on an async runtime: the file descriptors of the display are waited on
+    /// by a background thread, which is started on first use.
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// - On Wayland, this resolves once the compositor releases a buffer.
+    /// - On X11, this resolves once the server is done with the last upload. If softbuffer's own
+    ///   connection can't draw to the window, this still blocks on the round trip that waits for
+    ///   it.
+    /// - On platforms other than Linux and the BSDs, this is the same as [`Surface::buffer_mut`].
+    #[cfg(feature = "async")]
+    pub async fn buffer_mut_async(&mut self) -> Result<Buffer<'_, D, W>, SoftBufferError> {
+        #[cfg(free_unix)]
+        while !self.surface_impl.wait_for_buffer(Some(Duration::ZERO))? {
+            let Some(fd) = self.surface_impl.readiness_fd() else {
+                break;
+            };
+            reactor::readable(fd)
+                .await
+                .swbuf_err("Failed to wait for a buffer")?;
+        }
+        self.buffer_mut()
+    }
+}
+
+impl Surface<NoDisplayHandle, NoWindowHandle> {
+    /// Creates a new surface of the given size that renders into memory.
+    ///
+    /// The surface behaves like one on a real window: [`Surface::buffer_mut`] hands out one of
+    /// two buffers with the matching [`Buffer::age`], and [`Surface::fetch`] returns the last
+    /// presented frame. Every presented frame is recorded, see [`Surface::presented_frames`].
+    pub fn new_headless(width: NonZeroU32, height: NonZeroU32) -> Result<Self, SoftBufferError> {
+        let mut surface = Self::from_dispatch(SurfaceDispatch::Headless(
+            backends::headless::HeadlessImpl::from_window(NoWindowHandle(())),
+        ));
+        surface.resize(width, height)?;
+        Ok(surface)
     }
 }
 
@@ -161,14 +731,19 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> HasWindowHandle for Surface<D, W>
 ///
 /// This derefs to a `[u32]`, which depending on the backend may be a mapping into shared memory
 /// accessible to the display server, so presentation doesn't require any (client-side) copying.
+/// If the [`Surface::format`] isn't supported natively, this is a separate buffer that is
+/// converted when it is presented instead.
 ///
 /// This trusts the display server not to mutate the buffer, which could otherwise be unsound.
 ///
 /// # Data representation
 ///
-/// The format of the buffer is as follows. There is one `u32` in the buffer for each pixel in
+/// The format of the buffer is as follows, unless a different [`PixelFormat`] was set with
+/// [`Surface::set_format`]. There is one `u32` in the buffer for each pixel in
 /// the area to draw. The first entry is the upper-left most pixel. The second is one to the right
-/// etc. (Row-major top to bottom left to right one `u32` per pixel). Within each `u32` the highest
+/// etc. (Row-major top to bottom left to right one `u32` per pixel). Rows start
+/// [`Buffer::stride`] pixels apart, which may be more than the width of the surface if the
+/// backend pads its rows; [`Buffer::row_mut`] skips the padding. Within each `u32` the highest
 /// order 8 bits are to be set to 0. The next highest order 8 bits are the red channel, then the
 /// green channel, and then the blue channel in the lowest-order 8 bits. See the examples for
 /// one way to build this format using bitwise operations.
@@ -196,9 +771,52 @@ impl<D: HasDisplayHandle, W: HasWindowHandle> HasWindowHandle for Surface<D, W>
 /// - macOS
 pub struct Buffer<'a, D, W> {
     buffer_impl: BufferDispatch<'a, D, W>,
+
+    /// Set if the caller renders into a buffer that has to be converted before presenting.
+    conversion: Option<Conversion<'a>>,
+
+    /// The width of the buffer.
+    width: NonZeroU32,
+
+    /// The height of the buffer.
+    height: NonZeroU32,
+
+    /// The surface's damage history, which this buffer's damage is recorded into.
+    damage_history: Option<&'a mut DamageHistory>,
+
+    /// The damage history used to preserve contents, if enabled.
+    preserve_history: Option<&'a mut DamageHistory>,
+
+    /// The out of date part of the buffer, if the damage history is enabled.
+    repair_region: Option<Region>,
+
+    /// Set if the last frame was copied into the buffer.
+    preserved: bool,
+
+    /// The surface's copy of the last frame, for detecting damage.
+    last_frame: &'a mut Option<Vec<u32>>,
+
+    /// What to do with damage outside of the buffer.
+    damage_policy: DamagePolicy,
+
     _marker: PhantomData<(Arc<D>, Cell<()>)>,
 }
 
+/// The state needed to convert a buffer into the backend's format.
+struct Conversion<'a> {
+    /// The buffer the caller renders into.
+    buffer: &'a mut ConversionBuffer,
+
+    /// The format of `buffer`.
+    from: PixelFormat,
+
+    /// The format of the backend's buffer.
+    to: PixelFormat,
+
+    /// Dither when `to` has fewer bits per channel.
+    dither: bool,
+}
+
 impl<'a, D: HasDisplayHandle, W: HasWindowHandle> Buffer<'a, D, W> {
     /// Is age is the number of frames ago this buffer was last presented. So if the value is
     /// `1`, it is the same as the last frame, and if it is `2`, it is the same as the frame
@@ -207,7 +825,195 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> Buffer<'a, D, W> {
     ///
     /// This can be used to update only a portion of the buffer.
     pub fn age(&self) -> u8 {
-        self.buffer_impl.age()
+        match &self.conversion {
+            Some(conversion) => conversion.buffer.age(),
+            None if self.preserved => 1,
+            None => self.buffer_impl.age(),
+        }
+    }
+
+    /// The part of the buffer that is older than the last presented frame, and has to be redrawn
+    /// along with the damage of the new frame.
+    ///
+    /// This is empty if the buffer holds the last frame, and the whole buffer if its age is `0`
+    /// or older than the damage history. Returns `None` unless the history is enabled with
+    /// [`Surface::set_damage_history`].
+    pub fn repair_region(&self) -> Option<&Region> {
+        self.repair_region.as_ref()
+    }
+
+    /// The width of the buffer, as set by [`Surface::resize`].
+    pub fn width(&self) -> NonZeroU32 {
+        self.width
+    }
+
+    /// The height of the buffer, as set by [`Surface::resize`].
+    pub fn height(&self) -> NonZeroU32 {
+        self.height
+    }
+
+    /// The number of pixels from the start of one row of the buffer to the start of the next.
+    ///
+    /// This is at least the width of the surface, but can be more if the backend pads its rows,
+    /// for example to the pitch required by the hardware with DRM/KMS. The pixel at `(x, y)` is
+    /// at index `y * stride + x`.
+    pub fn stride(&self) -> usize {
+        match &self.conversion {
+            Some(_) => self.width.get() as usize,
+            None => self
+                .buffer_impl
+                .stride()
+                .unwrap_or(self.width.get() as usize),
+        }
+    }
+
+    /// Get row `y` of the buffer, without any padding.
+    ///
+    /// Returns `None` if `y` is outside of the buffer.
+    pub fn row(&self, y: u32) -> Option<&[u32]> {
+        let range = self.row_range(y)?;
+        Some(&self[range])
+    }
+
+    /// Get row `y` of the buffer mutably, without any padding.
+    ///
+    /// Returns `None` if `y` is outside of the buffer.
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [u32]> {
+        let range = self.row_range(y)?;
+        Some(&mut self[range])
+    }
+
+    /// Iterate over the rows of the buffer from top to bottom, without any padding.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &[u32]> + '_ {
+        let (width, height) = (self.width.get() as usize, self.height.get() as usize);
+        self.chunks(self.stride())
+            .take(height)
+            .map(move |row| &row[..width])
+    }
+
+    /// Iterate mutably over the rows of the buffer from top to bottom, without any padding.
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator<Item = &mut [u32]> + '_ {
+        let (width, height) = (self.width.get() as usize, self.height.get() as usize);
+        let stride = self.stride();
+        self.chunks_mut(stride)
+            .take(height)
+            .map(move |row| &mut row[..width])
+    }
+
+    /// Get the pixel at `(x, y)`.
+    ///
+    /// Returns `None` if the position is outside of the buffer.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<&u32> {
+        self.row(y)?.get(x as usize)
+    }
+
+    /// Get the pixel at `(x, y)` mutably.
+    ///
+    /// Returns `None` if the position is outside of the buffer.
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> Option<&mut u32> {
+        self.row_mut(y)?.get_mut(x as usize)
+    }
+
+    /// Iterate mutably over every pixel of the buffer along with its `(x, y)` position, row by
+    /// row.
+    ///
+    /// ```no_run
+    /// # fn draw<D: raw_window_handle::HasDisplayHandle, W: raw_window_handle::HasWindowHandle>(
+    /// #     buffer: &mut softbuffer::Buffer<'_, D, W>,
+    /// # ) {
+    /// for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+    ///     let red = x % 255;
+    ///     let green = y % 255;
+    ///     *pixel = (red << 16) | (green << 8);
+    /// }
+    /// # }
+    /// ```
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut u32)> + '_ {
+        self.rows_mut().enumerate().flat_map(|(y, row)| {
+            row.iter_mut()
+                .enumerate()
+                .map(move |(x, pixel)| (x as u32, y as u32, pixel))
+        })
+    }
+
+    /// Get a view of the pixels in `rect`, which can be written to independently of the rest of
+    /// the buffer.
+    ///
+    /// The view can be split into disjoint parts that can be drawn to on different threads, see
+    /// [`SubBuffer`]. Returns `None` if `rect` isn't entirely within the buffer.
+    pub fn region_mut(&mut self, rect: Rect) -> Option<SubBuffer<'_>> {
+        if rect.x.checked_add(rect.width.get())? > self.width.get()
+            || rect.y.checked_add(rect.height.get())? > self.height.get()
+        {
+            return None;
+        }
+
+        let stride = self.stride();
+        SubBuffer::new(self, stride, rect)
+    }
+
+    /// Split the buffer into at most `n` bands of rows, from top to bottom, that can be drawn to
+    /// on different threads.
+    ///
+    /// The bands are as close to the same height as possible, and there are fewer than `n` if
+    /// the buffer is less than `n` rows high. [`SubBuffer::rect`] gives the position of each
+    /// band, which can be passed to [`Buffer::present_with_damage`] afterwards.
+    ///
+    /// ```no_run
+    /// # fn draw<D: raw_window_handle::HasDisplayHandle, W: raw_window_handle::HasWindowHandle>(
+    /// #     mut buffer: softbuffer::Buffer<'_, D, W>,
+    /// # ) {
+    /// let mut damage = Vec::new();
+    /// std::thread::scope(|scope| {
+    ///     for mut band in buffer.split_rows_mut(4) {
+    ///         damage.push(band.rect());
+    ///         scope.spawn(move || band.fill(0x00ff0000));
+    ///     }
+    /// });
+    /// buffer.present_with_damage(&damage).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is `0`.
+    pub fn split_rows_mut(&mut self, n: usize) -> Vec<SubBuffer<'_>> {
+        assert!(n != 0, "can't split a buffer into zero bands");
+
+        let (width, height) = (self.width, self.height);
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        let mut rest = self
+            .region_mut(full)
+            .expect("the whole buffer is a valid region");
+
+        // The first `height % n` bands get one extra row.
+        let bands = n.min(height.get() as usize);
+        let (band_height, extra) = (height.get() as usize / bands, height.get() as usize % bands);
+
+        let mut split = Vec::with_capacity(bands);
+        for i in 0..bands - 1 {
+            let rows = band_height + usize::from(i < extra);
+            let (band, remainder) = rest.split_at_row(rows as u32);
+            split.push(band);
+            rest = remainder;
+        }
+        split.push(rest);
+        split
+    }
+
+    /// The range of indices that make up row `y`.
+    fn row_range(&self, y: u32) -> Option<ops::Range<usize>> {
+        if y >= self.height.get() {
+            return None;
+        }
+
+        let start = y as usize * self.stride();
+        Some(start..start + self.width.get() as usize)
     }
 
     /// Presents buffer to the window.
@@ -222,8 +1028,67 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> Buffer<'a, D, W> {
     ///
     /// If the caller wishes to synchronize other surface/window changes, such requests must be sent to the
     /// Wayland compositor before calling this function.
-    pub fn present(self) -> Result<(), SoftBufferError> {
-        self.buffer_impl.present()
+    pub fn present(mut self) -> Result<(), SoftBufferError> {
+        // The copy of the last frame only follows frames presented with automatic damage.
+        *self.last_frame = None;
+
+        if let Some(conversion) = &mut self.conversion {
+            let full = conversion.buffer.full_rect();
+            let stride = self
+                .buffer_impl
+                .stride()
+                .unwrap_or(self.width.get() as usize);
+            conversion.buffer.convert_into(
+                conversion.from,
+                conversion.to,
+                self.buffer_impl.pixels_mut(),
+                stride,
+                &[full],
+                conversion.dither,
+            );
+        }
+
+        self.buffer_impl.present()?;
+        record_damage([self.damage_history, self.preserve_history], || {
+            Region::from(Rect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            })
+        });
+        Ok(())
+    }
+
+    /// Presents buffer to the window, and requests to be told when the display is ready for the
+    /// next frame, see [`Surface::request_frame`].
+    pub fn present_and_request_frame(mut self) -> Result<(), SoftBufferError> {
+        self.buffer_impl.request_frame()?;
+        self.present()
+    }
+
+    /// Like [`Buffer::present`], but resolves once the frame is done and the next buffer can be
+    /// taken without waiting.
+    ///
+    /// This is on the buffer rather than the surface because the buffer borrows the surface until
+    /// it is presented.
+    ///
+    /// ## Platform Dependent Behavior
+    ///
+    /// - On Wayland, this resolves once the compositor releases the buffer that was on screen.
+    /// - On KMS, this resolves on the page flip. Other page flip events on the same device are
+    ///   consumed while waiting.
+    /// - On every other platform, this resolves right after presenting.
+    #[cfg(feature = "async")]
+    pub async fn present_async(self) -> Result<(), SoftBufferError> {
+        #[cfg(free_unix)]
+        let waiter = self.buffer_impl.frame_waiter()?;
+        self.present()?;
+        #[cfg(free_unix)]
+        if let Some(waiter) = waiter {
+            reactor::wait_for_frame(waiter).await?;
+        }
+        Ok(())
     }
 
     /// Presents buffer to the window, with damage regions.
@@ -237,8 +1102,97 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> Buffer<'a, D, W> {
     /// - Web
     ///
     /// Otherwise this is equivalent to [`Self::present`].
+    ///
+    /// A [`Region`] can be passed as the damage. Overlapping rects are merged, and rects that are
+    /// close together may be presented as their bounding box. Damage outside of the buffer is
+    /// clipped or rejected depending on the [`Surface::damage_policy`].
     pub fn present_with_damage(self, damage: &[Rect]) -> Result<(), SoftBufferError> {
-        self.buffer_impl.present_with_damage(damage)
+        *self.last_frame = None;
+        self.present_damage(damage)
+    }
+
+    /// Presents buffer to the window, with the damage found by comparing it to the last frame.
+    ///
+    /// The buffer is compared to a copy of the last frame in tiles of 16x16 pixels, and only the
+    /// tiles that changed are presented as damage, like with [`Self::present_with_damage`]. This
+    /// helps renderers that don't know what they changed, at the cost of keeping that copy and
+    /// comparing every pixel.
+    ///
+    /// The first frame, and frames after one presented otherwise or after a resize, are damaged
+    /// completely.
+    pub fn present_auto_damage(self) -> Result<(), SoftBufferError> {
+        let (width, height) = (self.width, self.height);
+        let len = width.get() as usize * height.get() as usize;
+
+        let damage = match self.last_frame.take() {
+            Some(mut last_frame) if last_frame.len() == len => {
+                let damage =
+                    region::detect_damage(&mut last_frame, &self, self.stride(), width, height);
+                *self.last_frame = Some(last_frame);
+                damage
+            }
+            _ => {
+                let last_frame = self.rows().flatten().copied().collect();
+                *self.last_frame = Some(last_frame);
+                Region::from(Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                })
+            }
+        };
+
+        self.present_damage(&damage)
+    }
+
+    /// Present with damage, keeping the copy of the last frame.
+    fn present_damage(mut self, damage: &[Rect]) -> Result<(), SoftBufferError> {
+        let damage = region::check_damage(damage, self.width, self.height, self.damage_policy)?;
+        let damage = &*region::simplify_damage(&damage);
+
+        if let Some(conversion) = &mut self.conversion {
+            // Only the damage needs converting if the backend's buffer holds the last frame.
+            let full = [conversion.buffer.full_rect()];
+            let converted = if self.buffer_impl.age() == 1 {
+                damage
+            } else {
+                &full
+            };
+            let stride = self
+                .buffer_impl
+                .stride()
+                .unwrap_or(self.width.get() as usize);
+            conversion.buffer.convert_into(
+                conversion.from,
+                conversion.to,
+                self.buffer_impl.pixels_mut(),
+                stride,
+                converted,
+                conversion.dither,
+            );
+        }
+
+        self.buffer_impl.present_with_damage(damage)?;
+        record_damage([self.damage_history, self.preserve_history], || {
+            damage
+                .iter()
+                .collect::<Region>()
+                .clip(self.width, self.height)
+        });
+        Ok(())
+    }
+}
+
+/// Record the damage of a presented frame into the damage histories that are enabled.
+fn record_damage(histories: [Option<&mut DamageHistory>; 2], damage: impl FnOnce() -> Region) {
+    if histories.iter().all(Option::is_none) {
+        return;
+    }
+
+    let damage = damage();
+    for history in histories.into_iter().flatten() {
+        history.record(damage.clone());
     }
 }
 
@@ -247,14 +1201,20 @@ impl<'a, D: HasDisplayHandle, W: HasWindowHandle> ops::Deref for Buffer<'a, D, W
 
     #[inline]
     fn deref(&self) -> &[u32] {
-        self.buffer_impl.pixels()
+        match &self.conversion {
+            Some(conversion) => conversion.buffer.pixels(),
+            None => self.buffer_impl.pixels(),
+        }
     }
 }
 
 impl<'a, D: HasDisplayHandle, W: HasWindowHandle> ops::DerefMut for Buffer<'a, D, W> {
     #[inline]
     fn deref_mut(&mut self) -> &mut [u32] {
-        self.buffer_impl.pixels_mut()
+        match &mut self.conversion {
+            Some(conversion) => conversion.buffer.pixels_mut(),
+            None => self.buffer_impl.pixels_mut(),
+        }
     }
 }
 
@@ -344,3 +1304,192 @@ fn __assert_send() {
     /// ```
     fn __buffer_not_sync() {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_surface;
+    use raw_window_handle::{DisplayHandle, WebDisplayHandle};
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        }
+    }
+
+    #[test]
+    fn damage_out_of_range() {
+        let mut surface = test_surface(2, 2);
+
+        let damage = rect(u32::MAX, 0, 2, 1);
+        surface
+            .buffer_mut()
+            .unwrap()
+            .present_with_damage(&[damage])
+            .unwrap();
+        assert!(surface.presented_frames()[0].damage.is_empty());
+
+        surface.set_damage_policy(DamagePolicy::Strict);
+        let result = surface.buffer_mut().unwrap().present_with_damage(&[damage]);
+        assert!(matches!(
+            result,
+            Err(SoftBufferError::DamageOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn rows_skip_nothing_without_padding() {
+        let mut surface = test_surface(3, 2);
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        assert_eq!(buffer.stride(), 3);
+        buffer.row_mut(1).unwrap().fill(7);
+        assert!(buffer.row_mut(2).is_none());
+        assert_eq!(buffer.row(0).unwrap(), [0, 0, 0]);
+        assert_eq!(*buffer, [0, 0, 0, 7, 7, 7]);
+    }
+
+    #[test]
+    fn pixels_by_position() {
+        let mut surface = test_surface(2, 3);
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        assert_eq!((buffer.width().get(), buffer.height().get()), (2, 3));
+        for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+            *pixel = y * 10 + x;
+        }
+
+        assert_eq!(buffer.pixel(1, 2), Some(&21));
+        assert_eq!(buffer.pixel(2, 0), None);
+        *buffer.pixel_mut(0, 1).unwrap() = 5;
+        assert_eq!(buffer.rows().len(), 3);
+        assert_eq!(buffer.rows().nth(1).unwrap(), [5, 11]);
+        assert!(buffer.rows_mut().all(|row| row.len() == 2));
+    }
+
+    #[test]
+    fn preserved_contents_carry_over() {
+        let mut surface = test_surface(2, 2);
+        surface.set_preserve_contents(true);
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer.copy_from_slice(&[1, 2, 3, 4]);
+        buffer.present().unwrap();
+
+        // The second buffer is new, so the whole frame is copied.
+        let mut buffer = surface.buffer_mut().unwrap();
+        assert_eq!((buffer.age(), &*buffer), (1, &[1, 2, 3, 4][..]));
+        *buffer.pixel_mut(1, 0).unwrap() = 5;
+        let damage = rect(1, 0, 1, 1);
+        buffer.present_with_damage(&[damage]).unwrap();
+
+        // The first buffer only misses the damage of the second frame.
+        let buffer = surface.buffer_mut().unwrap();
+        assert_eq!((buffer.age(), &*buffer), (1, &[1, 5, 3, 4][..]));
+    }
+
+    #[test]
+    fn auto_damage_finds_changed_tiles() {
+        let mut surface = test_surface(40, 20);
+
+        surface.buffer_mut().unwrap().present_auto_damage().unwrap();
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer.fill(0);
+        *buffer.pixel_mut(35, 17).unwrap() = 1;
+        buffer.present_auto_damage().unwrap();
+
+        let mut buffer = surface.buffer_mut().unwrap();
+        buffer.fill(0);
+        *buffer.pixel_mut(35, 17).unwrap() = 1;
+        buffer.present_auto_damage().unwrap();
+
+        let frames = surface.take_presented_frames();
+        let damage = frames
+            .iter()
+            .map(|frame| frame.damage.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            damage,
+            [&[rect(0, 0, 40, 20)][..], &[rect(32, 16, 8, 4)], &[]]
+        );
+    }
+
+    #[test]
+    fn headless_backend_can_be_forced() {
+        // SAFETY: A web display handle has no data that could dangle.
+        let display =
+            unsafe { DisplayHandle::borrow_raw(RawDisplayHandle::Web(WebDisplayHandle::new())) };
+
+        let context = Context::new_with_backend(display, Backend::Headless).unwrap();
+        assert_eq!(context.backend(), Backend::Headless);
+        assert_eq!(Context::headless().backend(), Backend::Headless);
+
+        // It is never picked otherwise.
+        #[cfg(not(target_family = "wasm"))]
+        assert!(Context::new_excluding_backends(display, &[]).is_err());
+    }
+
+    #[test]
+    fn size_not_set() {
+        let context = Context::headless();
+        let mut surface = Surface::new(&context, NoWindowHandle(())).unwrap();
+
+        assert!(matches!(
+            surface.buffer_mut(),
+            Err(SoftBufferError::SizeNotSet)
+        ));
+        assert!(matches!(surface.fetch(), Err(SoftBufferError::SizeNotSet)));
+    }
+
+    #[test]
+    fn buffers_can_be_taken_without_waiting() {
+        let mut surface = test_surface(2, 2);
+
+        surface.try_buffer_mut().unwrap().present().unwrap();
+        let buffer = surface.buffer_mut_timeout(Duration::ZERO).unwrap();
+        assert_eq!(buffer.age(), 0);
+        buffer.present().unwrap();
+        assert_eq!(surface.try_buffer_mut().unwrap().age(), 2);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn buffers_can_be_taken_asynchronously() {
+        use std::future::Future;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+            let mut cx = Context::from_waker(&waker);
+            let mut future = std::pin::pin!(future);
+            loop {
+                match future.as_mut().poll(&mut cx) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => std::thread::park(),
+                }
+            }
+        }
+
+        let mut surface = test_surface(2, 2);
+
+        block_on(async {
+            let buffer = surface.buffer_mut_async().await.unwrap();
+            assert_eq!(buffer.age(), 0);
+            buffer.present_async().await.unwrap();
+        });
+        assert_eq!(surface.presented_frames().len(), 1);
+    }
+}
@@ -0,0 +1,201 @@
+//! Waiting for file descriptors from futures, without depending on an async runtime.
+//!
+//! A single background thread waits for every registered file descriptor, and wakes the futures
+//! waiting on it once it becomes readable.
+
+use crate::error::SwResultExt;
+use crate::SoftBufferError;
+use polling::{Event, Events, Poller};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+type Waiting = Mutex<HashMap<RawFd, Vec<(u64, Waker)>>>;
+
+struct Reactor {
+    poller: Arc<Poller>,
+
+    /// The futures waiting on each file descriptor that is registered with the poller.
+    waiting: Arc<Waiting>,
+}
+
+impl Reactor {
+    /// Get the reactor, starting its thread on first use.
+    fn get() -> io::Result<&'static Self> {
+        static REACTOR: OnceLock<Result<Reactor, io::ErrorKind>> = OnceLock::new();
+
+        let reactor = REACTOR.get_or_init(|| {
+            let reactor = Reactor {
+                poller: Arc::new(Poller::new().map_err(|err| err.kind())?),
+                waiting: Arc::default(),
+            };
+            let (poller, waiting) = (reactor.poller.clone(), reactor.waiting.clone());
+            thread::Builder::new()
+                .name("softbuffer-reactor".into())
+                .spawn(move || run(&poller, &waiting))
+                .map_err(|err| err.kind())?;
+            Ok(reactor)
+        });
+        reactor.as_ref().map_err(|&kind| kind.into())
+    }
+}
+
+fn run(poller: &Poller, waiting: &Waiting) {
+    let mut events = Events::new();
+    loop {
+        events.clear();
+        if let Err(err) = poller.wait(&mut events, None) {
+            if err.kind() != io::ErrorKind::Interrupted {
+                log::error!("softbuffer reactor failed to wait: {}", err);
+            }
+            continue;
+        }
+
+        let mut woken = Vec::new();
+        {
+            let mut waiting = waiting.lock().unwrap_or_else(|x| x.into_inner());
+            for event in events.iter() {
+                let fd = event.key as RawFd;
+                if let Some(wakers) = waiting.remove(&fd) {
+                    // SAFETY: The futures that registered `fd` borrow it until they are dropped,
+                    // and they can only be dropped after they are removed under this lock.
+                    let _ = poller.delete(unsafe { BorrowedFd::borrow_raw(fd) });
+                    woken.extend(wakers.into_iter().map(|(_, waker)| waker));
+                }
+            }
+        }
+        woken.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Wait until `fd` is readable.
+pub(crate) fn readable(fd: BorrowedFd<'_>) -> Readable<'_> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    Readable {
+        fd,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        registered: false,
+    }
+}
+
+/// The future returned by [`readable`].
+pub(crate) struct Readable<'a> {
+    fd: BorrowedFd<'a>,
+    id: u64,
+    registered: bool,
+}
+
+impl Future for Readable<'_> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let reactor = Reactor::get()?;
+        let fd = self.fd.as_raw_fd();
+        let id = self.id;
+
+        let mut waiting = reactor.waiting.lock().unwrap_or_else(|x| x.into_inner());
+        match waiting.get_mut(&fd) {
+            Some(wakers) => match wakers.iter_mut().find(|(other, _)| *other == id) {
+                Some((_, waker)) => waker.clone_from(cx.waker()),
+                None if self.registered => return Poll::Ready(Ok(())),
+                None => wakers.push((id, cx.waker().clone())),
+            },
+            // The reactor removes the file descriptor once it is readable.
+            None if self.registered => return Poll::Ready(Ok(())),
+            None => {
+                // SAFETY: `fd` is deleted from the poller by the reactor once it fires, or when
+                // this future is dropped, which is before the borrow of `fd` ends.
+                unsafe { reactor.poller.add(fd, Event::readable(fd as usize))? };
+                waiting.insert(fd, vec![(id, cx.waker().clone())]);
+            }
+        }
+
+        self.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Readable<'_> {
+    fn drop(&mut self) {
+        if !self.registered {
+            return;
+        }
+        let Ok(reactor) = Reactor::get() else {
+            return;
+        };
+
+        let fd = self.fd.as_raw_fd();
+        let mut waiting = reactor.waiting.lock().unwrap_or_else(|x| x.into_inner());
+        if let Some(wakers) = waiting.get_mut(&fd) {
+            wakers.retain(|(id, _)| *id != self.id);
+            if wakers.is_empty() {
+                waiting.remove(&fd);
+                let _ = reactor.poller.delete(self.fd);
+            }
+        }
+    }
+}
+
+/// Waits for a presented buffer to be shown, see [`wait_for_frame`].
+pub(crate) trait FrameWait {
+    /// The file descriptor that becomes readable when [`FrameWait::is_done`] should be checked.
+    fn fd(&self) -> BorrowedFd<'_>;
+
+    /// Process pending events, and check if the frame has been shown.
+    fn is_done(&mut self) -> Result<bool, SoftBufferError>;
+}
+
+/// Wait until `waiter` is done.
+pub(crate) async fn wait_for_frame(
+    mut waiter: Box<dyn FrameWait + '_>,
+) -> Result<(), SoftBufferError> {
+    while !waiter.is_done()? {
+        readable(waiter.fd())
+            .await
+            .swbuf_err("Failed to wait for the frame")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+    use std::task::Wake;
+
+    struct Flag(Mutex<bool>, std::sync::Condvar);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            *self.0.lock().unwrap() = true;
+            self.1.notify_all();
+        }
+    }
+
+    #[test]
+    fn wakes_when_readable() {
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        let flag = Arc::new(Flag(Mutex::new(false), Default::default()));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = readable(reader.as_fd());
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+        writer.write_all(&[0]).unwrap();
+        let mut woken = flag.0.lock().unwrap();
+        while !*woken {
+            woken = flag.1.wait(woken).unwrap();
+        }
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+    }
+}
@@ -0,0 +1,783 @@
+//! Sets of pixels made up of rectangles, for damage tracking.
+
+use crate::{Rect, SoftBufferError};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::ops;
+
+/// Damage lists longer than this are presented as their bounding box.
+const MAX_DAMAGE_RECTS: usize = 16;
+
+/// The width and height of the tiles that frames are compared in.
+const TILE_SIZE: u32 = 16;
+
+/// What to do with damage that reaches outside of the surface.
+///
+/// Set with [`Surface::set_damage_policy`](crate::Surface::set_damage_policy).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DamagePolicy {
+    /// Fail with [`SoftBufferError::DamageOutOfRange`].
+    Strict,
+
+    /// Only present the part of the damage that is within the surface.
+    #[default]
+    Clip,
+}
+
+/// A set of pixels, stored as non-overlapping [`Rect`]s.
+///
+/// The rects are sorted into horizontal bands from top to bottom, and from left to right within
+/// each band. A region derefs to `[Rect]`, so it can be passed straight to
+/// [`Buffer::present_with_damage`](crate::Buffer::present_with_damage).
+///
+/// Parts of rects that don't fit into `u32` coordinates are dropped.
+///
+/// ```
+/// use softbuffer::{Rect, Region};
+/// use std::num::NonZeroU32;
+///
+/// let rect = |x, y, width, height| Rect {
+///     x,
+///     y,
+///     width: NonZeroU32::new(width).unwrap(),
+///     height: NonZeroU32::new(height).unwrap(),
+/// };
+///
+/// let mut region = Region::from(rect(0, 0, 10, 10));
+/// region = region.subtract(&rect(2, 2, 6, 6).into());
+/// assert_eq!(region.len(), 4);
+/// assert_eq!(region.area(), 100 - 36);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Region {
+    /// The rects, in bands.
+    rects: Vec<Rect>,
+}
+
+/// The horizontal spans of a band, as sorted, non-overlapping and non-adjacent `(start, end)`
+/// pairs.
+type Spans = Vec<(u32, u32)>;
+
+/// The rects of a region that share the same rows.
+struct Band<'a> {
+    top: u32,
+    bottom: u32,
+    rects: &'a [Rect],
+}
+
+impl Band<'_> {
+    fn spans(&self) -> Spans {
+        self.rects
+            .iter()
+            .map(|rect| (rect.x, right(rect)))
+            .collect()
+    }
+}
+
+impl Region {
+    /// Create an empty region.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// Get the rects that make up the region.
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    /// The number of pixels in the region.
+    pub fn area(&self) -> u64 {
+        self.rects.iter().map(area).sum()
+    }
+
+    /// The smallest rect that contains the whole region, or `None` if it is empty.
+    pub fn bounds(&self) -> Option<Rect> {
+        let first = self.rects.first()?;
+        let last = self.rects.last()?;
+        let left = self.rects.iter().map(|rect| rect.x).min()?;
+        let right = self.rects.iter().map(right).max()?;
+        Some(make_rect(left, first.y, right, bottom(last)).unwrap())
+    }
+
+    /// Check if the pixel at `(x, y)` is in the region.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.rects
+            .iter()
+            .any(|rect| (rect.x..right(rect)).contains(&x) && (rect.y..bottom(rect)).contains(&y))
+    }
+
+    /// The pixels in either region.
+    pub fn union(&self, other: &Region) -> Region {
+        combine(self, other, |a, b| a || b)
+    }
+
+    /// The pixels in both regions.
+    pub fn intersect(&self, other: &Region) -> Region {
+        combine(self, other, |a, b| a && b)
+    }
+
+    /// The pixels in this region, but not in `other`.
+    pub fn subtract(&self, other: &Region) -> Region {
+        combine(self, other, |a, b| a && !b)
+    }
+
+    /// Move the region by `dx` and `dy`.
+    ///
+    /// Parts that end up outside of `u32` coordinates are dropped.
+    pub fn translate(&self, dx: i64, dy: i64) -> Region {
+        let clamp = |value: u32, delta: i64| {
+            (i64::from(value) + delta).clamp(0, i64::from(u32::MAX)) as u32
+        };
+
+        // Bands and spans keep their order, but clamping can make them empty or the same.
+        let mut region = Region::new();
+        for band in self.bands() {
+            let (top, bottom) = (clamp(band.top, dy), clamp(band.bottom, dy));
+            if top == bottom {
+                continue;
+            }
+
+            let mut spans: Spans = Vec::new();
+            for (left, right) in band.spans() {
+                let (left, right) = (clamp(left, dx), clamp(right, dx));
+                match spans.last_mut() {
+                    _ if left == right => {}
+                    Some(last) if last.1 == left => last.1 = right,
+                    _ => spans.push((left, right)),
+                }
+            }
+            region.push_band(top, bottom, &spans);
+        }
+        region
+    }
+
+    /// The part of the region within a surface of the given size.
+    pub fn clip(&self, width: NonZeroU32, height: NonZeroU32) -> Region {
+        self.intersect(&Region::from(Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }))
+    }
+
+    /// The bands of the region, from top to bottom.
+    fn bands(&self) -> Vec<Band<'_>> {
+        let mut bands = Vec::new();
+        let mut rest = &self.rects[..];
+        while let Some(first) = rest.first() {
+            let len = rest.iter().take_while(|rect| rect.y == first.y).count();
+            let (rects, next) = rest.split_at(len);
+            bands.push(Band {
+                top: first.y,
+                bottom: bottom(first),
+                rects,
+            });
+            rest = next;
+        }
+        bands
+    }
+
+    /// Add a band of `spans` from `top` to `bottom`, below all existing bands.
+    fn push_band(&mut self, top: u32, bottom: u32, spans: &[(u32, u32)]) {
+        if spans.is_empty() {
+            return;
+        }
+
+        // Grow the last band instead if it is directly above and has the same spans.
+        if let Some(last) = self.rects.last() {
+            let last_top = last.y;
+            let band_len = self
+                .rects
+                .iter()
+                .rev()
+                .take_while(|rect| rect.y == last_top)
+                .count();
+            let band_start = self.rects.len() - band_len;
+            let last_band = &mut self.rects[band_start..];
+            if self::bottom(&last_band[0]) == top
+                && last_band.len() == spans.len()
+                && last_band
+                    .iter()
+                    .zip(spans)
+                    .all(|(rect, &(left, right))| rect.x == left && self::right(rect) == right)
+            {
+                for rect in last_band {
+                    rect.height = NonZeroU32::new(bottom - last_top).unwrap();
+                }
+                return;
+            }
+        }
+
+        self.rects.extend(
+            spans
+                .iter()
+                .filter_map(|&(left, right)| make_rect(left, top, right, bottom)),
+        );
+    }
+}
+
+impl From<Rect> for Region {
+    fn from(rect: Rect) -> Self {
+        let rects = make_rect(
+            rect.x,
+            rect.y,
+            rect.x.saturating_add(rect.width.get()),
+            rect.y.saturating_add(rect.height.get()),
+        );
+        Self {
+            rects: rects.into_iter().collect(),
+        }
+    }
+}
+
+impl FromIterator<Rect> for Region {
+    /// The union of all the rects.
+    fn from_iter<I: IntoIterator<Item = Rect>>(iter: I) -> Self {
+        // Merge in pairs, so that every rect only takes part in a logarithmic number of unions.
+        let mut regions = iter.into_iter().map(Region::from).collect::<Vec<_>>();
+        while regions.len() > 1 {
+            regions = regions
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => a.union(b),
+                    [a] => a.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        regions.pop().unwrap_or_default()
+    }
+}
+
+impl<'a> FromIterator<&'a Rect> for Region {
+    /// The union of all the rects.
+    fn from_iter<I: IntoIterator<Item = &'a Rect>>(iter: I) -> Self {
+        iter.into_iter().copied().collect()
+    }
+}
+
+impl ops::Deref for Region {
+    type Target = [Rect];
+
+    #[inline]
+    fn deref(&self) -> &[Rect] {
+        &self.rects
+    }
+}
+
+/// The damage of the last few presented frames, for repairing buffers that are older than one
+/// frame.
+///
+/// A buffer with an [age](crate::Buffer::age) of `n` holds the frame presented `n` frames ago,
+/// so on top of the damage of the new frame, everything damaged by the `n - 1` frames since has
+/// to be redrawn. Enable the history with [`Surface::set_damage_history`] and get that region
+/// from [`Buffer::repair_region`].
+///
+/// [`Surface::set_damage_history`]: crate::Surface::set_damage_history
+/// [`Buffer::repair_region`]: crate::Buffer::repair_region
+#[derive(Clone, Debug, Default)]
+pub struct DamageHistory {
+    /// The damage of each frame, newest first.
+    frames: VecDeque<Region>,
+
+    /// The number of frames to remember.
+    capacity: usize,
+}
+
+impl DamageHistory {
+    /// Create a history that remembers the damage of the last `capacity` frames.
+    ///
+    /// That is enough to repair buffers up to `capacity + 1` frames old.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// The number of frames the history remembers.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Record the damage of a newly presented frame, forgetting the oldest frame if the history
+    /// is full.
+    pub fn record(&mut self, damage: Region) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.frames.truncate(self.capacity - 1);
+        self.frames.push_front(damage);
+    }
+
+    /// Forget all frames, for example because the surface was resized.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// The region of a buffer with the given age that is out of date, on a surface of the given
+    /// size.
+    ///
+    /// This is the whole surface if `age` is `0`, or older than the history.
+    pub fn repair_region(&self, age: u8, width: NonZeroU32, height: NonZeroU32) -> Region {
+        let frames = usize::from(age).wrapping_sub(1);
+        if age == 0 || frames > self.frames.len() {
+            return Region::from(Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            });
+        }
+
+        self.frames
+            .iter()
+            .take(frames)
+            .fold(Region::new(), |repair, damage| repair.union(damage))
+            .clip(width, height)
+    }
+}
+
+/// Combine two regions, keeping the pixels for which `op` returns `true` given whether they are
+/// in `a` and in `b`.
+///
+/// Both regions are walked from top to bottom at the same time, so this takes time linear in the
+/// number of rects.
+fn combine(a: &Region, b: &Region, op: impl Fn(bool, bool) -> bool) -> Region {
+    let (a, b) = (a.bands(), b.bands());
+    let (mut i, mut j) = (0, 0);
+    let mut region = Region::new();
+    let mut y = 0;
+    while i < a.len() || j < b.len() {
+        let (next_a, next_b) = (a.get(i), b.get(j));
+
+        // Skip rows that are in neither region.
+        let top = next_a.iter().chain(&next_b).map(|band| band.top).min();
+        y = y.max(top.unwrap());
+
+        // The rows up to the next edge in either region are the same all the way down.
+        let in_a = next_a.filter(|band| band.top <= y);
+        let in_b = next_b.filter(|band| band.top <= y);
+        let edge = |next: Option<&Band<'_>>, inside: Option<&Band<'_>>| match inside {
+            Some(band) => Some(band.bottom),
+            None => next.map(|band| band.top),
+        };
+        let bottom = edge(next_a, in_a)
+            .into_iter()
+            .chain(edge(next_b, in_b))
+            .min()
+            .unwrap();
+
+        let spans = combine_spans(
+            &in_a.map(Band::spans).unwrap_or_default(),
+            &in_b.map(Band::spans).unwrap_or_default(),
+            &op,
+        );
+        region.push_band(y, bottom, &spans);
+
+        y = bottom;
+        if in_a.is_some_and(|band| band.bottom == y) {
+            i += 1;
+        }
+        if in_b.is_some_and(|band| band.bottom == y) {
+            j += 1;
+        }
+    }
+    region
+}
+
+/// Combine the spans of two bands, like [`combine`].
+fn combine_spans(a: &[(u32, u32)], b: &[(u32, u32)], op: impl Fn(bool, bool) -> bool) -> Spans {
+    // The `n`th edge of a band, going from left to right. Even edges start a span, odd ones end
+    // it.
+    let edge = |spans: &[(u32, u32)], n: usize| {
+        spans
+            .get(n / 2)
+            .map(|&(left, right)| if n % 2 == 0 { left } else { right })
+    };
+
+    let mut spans: Spans = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut left = edge(a, 0).into_iter().chain(edge(b, 0)).min();
+    while let Some(x) = left {
+        // Step over all edges at `x`, so that touching spans count as one.
+        while edge(a, i) == Some(x) {
+            i += 1;
+        }
+        while edge(b, j) == Some(x) {
+            j += 1;
+        }
+
+        let Some(right) = edge(a, i).into_iter().chain(edge(b, j)).min() else {
+            break;
+        };
+        if op(i % 2 == 1, j % 2 == 1) {
+            match spans.last_mut() {
+                Some(last) if last.1 == x => last.1 = right,
+                _ => spans.push((x, right)),
+            }
+        }
+        left = Some(right);
+    }
+    spans
+}
+
+/// Make sure `damage` lies within a surface of the given size, following `policy`.
+pub(crate) fn check_damage(
+    damage: &[Rect],
+    width: NonZeroU32,
+    height: NonZeroU32,
+    policy: DamagePolicy,
+) -> Result<Cow<'_, [Rect]>, SoftBufferError> {
+    let fits = |rect: &Rect| {
+        rect.x
+            .checked_add(rect.width.get())
+            .is_some_and(|right| right <= width.get())
+            && rect
+                .y
+                .checked_add(rect.height.get())
+                .is_some_and(|bottom| bottom <= height.get())
+    };
+
+    match damage.iter().find(|rect| !fits(rect)) {
+        None => Ok(Cow::Borrowed(damage)),
+        Some(&rect) if policy == DamagePolicy::Strict => {
+            Err(SoftBufferError::DamageOutOfRange { rect })
+        }
+        Some(_) => {
+            let surface = Region::from(Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            });
+            Ok(Cow::Owned(
+                damage
+                    .iter()
+                    .flat_map(|&rect| Region::from(rect).intersect(&surface).rects)
+                    .collect(),
+            ))
+        }
+    }
+}
+
+/// Simplify damage into a shorter list of rects that covers at least the same pixels.
+///
+/// Overlaps are removed, and rects that are close together, like the one pixel high slivers of
+/// a rounded shape, are merged into their bounding box if that doesn't add much area. Long
+/// lists collapse into a single bounding box, as every rect costs a request on most backends.
+///
+/// The damage must have gone through [`check_damage`] first.
+pub(crate) fn simplify_damage(damage: &[Rect]) -> Cow<'_, [Rect]> {
+    if damage.len() <= 1 {
+        return Cow::Borrowed(damage);
+    }
+    if damage.len() > MAX_DAMAGE_RECTS {
+        // Not worth building a region for, it would only be thrown away.
+        let bounds = damage.iter().copied().reduce(|a, b| bounding_box(&a, &b));
+        return Cow::Owned(bounds.into_iter().collect());
+    }
+
+    let region = damage.iter().collect::<Region>();
+
+    let mut simplified: Vec<Rect> = Vec::with_capacity(region.len());
+    for rect in region.rects() {
+        if let Some(last) = simplified.last_mut() {
+            // Merge if the bounding box wastes at most a quarter of the area.
+            let merged = bounding_box(last, rect);
+            if area(&merged) * 4 <= (area(last) + area(rect)) * 5 {
+                *last = merged;
+                continue;
+            }
+        }
+        simplified.push(*rect);
+    }
+
+    if simplified.len() > MAX_DAMAGE_RECTS {
+        simplified = region.bounds().into_iter().collect();
+    }
+    Cow::Owned(simplified)
+}
+
+/// Find the tiles in which the frame `new` differs from the last frame `old`, and copy them into
+/// `old`.
+///
+/// `old` holds `width * height` pixels without padding, while the rows of `new` are `stride`
+/// pixels apart.
+pub(crate) fn detect_damage(
+    old: &mut [u32],
+    new: &[u32],
+    stride: usize,
+    width: NonZeroU32,
+    height: NonZeroU32,
+) -> Region {
+    let (width, height) = (width.get(), height.get());
+    let row = |stride: usize, y: u32, left: u32, right: u32| {
+        let start = y as usize * stride;
+        start + left as usize..start + right as usize
+    };
+
+    let mut region = Region::new();
+    for top in (0..height).step_by(TILE_SIZE as usize) {
+        let bottom = (top + TILE_SIZE).min(height);
+
+        let mut spans: Spans = Vec::new();
+        for left in (0..width).step_by(TILE_SIZE as usize) {
+            let right = (left + TILE_SIZE).min(width);
+            let changed = (top..bottom).any(|y| {
+                old[row(width as usize, y, left, right)] != new[row(stride, y, left, right)]
+            });
+            if !changed {
+                continue;
+            }
+
+            for y in top..bottom {
+                old[row(width as usize, y, left, right)]
+                    .copy_from_slice(&new[row(stride, y, left, right)]);
+            }
+            match spans.last_mut() {
+                Some(last) if last.1 == left => last.1 = right,
+                _ => spans.push((left, right)),
+            }
+        }
+        region.push_band(top, bottom, &spans);
+    }
+    region
+}
+
+/// The number of pixels in `rect`.
+fn area(rect: &Rect) -> u64 {
+    u64::from(rect.width.get()) * u64::from(rect.height.get())
+}
+
+/// The x coordinate just right of `rect`.
+fn right(rect: &Rect) -> u32 {
+    rect.x + rect.width.get()
+}
+
+/// The y coordinate just below `rect`.
+fn bottom(rect: &Rect) -> u32 {
+    rect.y + rect.height.get()
+}
+
+/// The smallest rect that contains both `a` and `b`.
+fn bounding_box(a: &Rect, b: &Rect) -> Rect {
+    make_rect(
+        a.x.min(b.x),
+        a.y.min(b.y),
+        right(a).max(right(b)),
+        bottom(a).max(bottom(b)),
+    )
+    .unwrap()
+}
+
+/// Make a rect from its edges, or `None` if it would be empty.
+fn make_rect(left: u32, top: u32, right: u32, bottom: u32) -> Option<Rect> {
+    Some(Rect {
+        x: left,
+        y: top,
+        width: NonZeroU32::new(right.checked_sub(left)?)?,
+        height: NonZeroU32::new(bottom.checked_sub(top)?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_surface;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        }
+    }
+
+    #[test]
+    fn union_removes_overlap() {
+        let region = Region::from(rect(0, 0, 4, 4)).union(&rect(2, 2, 4, 4).into());
+        assert_eq!(
+            region.rects(),
+            [rect(0, 0, 4, 2), rect(0, 2, 6, 2), rect(2, 4, 4, 2)]
+        );
+        assert_eq!(region.area(), 16 + 16 - 4);
+        assert_eq!(region.bounds(), Some(rect(0, 0, 6, 6)));
+
+        // Adjacent rects with the same spans merge.
+        let region = [rect(0, 0, 4, 2), rect(0, 2, 4, 2)]
+            .into_iter()
+            .collect::<Region>();
+        assert_eq!(region.rects(), [rect(0, 0, 4, 4)]);
+    }
+
+    #[test]
+    fn intersect_and_subtract() {
+        let a = Region::from(rect(0, 0, 4, 4));
+        let b = Region::from(rect(2, 2, 4, 4));
+        assert_eq!(a.intersect(&b).rects(), [rect(2, 2, 2, 2)]);
+        assert_eq!(a.subtract(&b).rects(), [rect(0, 0, 4, 2), rect(0, 2, 2, 2)]);
+        assert!(a.subtract(&a).is_empty());
+        assert!(a.contains(3, 3) && !a.contains(4, 0));
+    }
+
+    #[test]
+    fn operations_match_pixels() {
+        // Scattered, overlapping and touching rects from a simple LCG.
+        let mut seed = 1u32;
+        let mut next = |max: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) % max
+        };
+        let mut random_region = || {
+            (0..12)
+                .map(|_| rect(next(12), next(12), next(5) + 1, next(5) + 1))
+                .collect::<Region>()
+        };
+
+        for _ in 0..20 {
+            let (a, b) = (random_region(), random_region());
+            let [union, intersection, difference] = [a.union(&b), a.intersect(&b), a.subtract(&b)];
+            let mut counts = [0; 3];
+            for (x, y) in (0..18).flat_map(|x| (0..18).map(move |y| (x, y))) {
+                let (in_a, in_b) = (a.contains(x, y), b.contains(x, y));
+                assert_eq!(union.contains(x, y), in_a || in_b);
+                assert_eq!(intersection.contains(x, y), in_a && in_b);
+                assert_eq!(difference.contains(x, y), in_a && !in_b);
+                counts[0] += u64::from(in_a || in_b);
+                counts[1] += u64::from(in_a && in_b);
+                counts[2] += u64::from(in_a && !in_b);
+            }
+
+            // No pixel is covered twice.
+            assert_eq!(
+                [union.area(), intersection.area(), difference.area()],
+                counts
+            );
+        }
+    }
+
+    #[test]
+    fn translate_and_clip() {
+        let region = Region::from(rect(2, 2, 4, 4));
+        assert_eq!(region.translate(-3, 1).rects(), [rect(0, 3, 3, 4)]);
+        assert_eq!(
+            region
+                .clip(NonZeroU32::new(4).unwrap(), NonZeroU32::new(3).unwrap())
+                .rects(),
+            [rect(2, 2, 2, 1)]
+        );
+        assert!(region.translate(-10, 0).is_empty());
+
+        // Bands that end up the same are merged, like in a region built from the same pixels.
+        let region = [rect(2, 0, 4, 2), rect(0, 2, 6, 2)]
+            .into_iter()
+            .collect::<Region>();
+        assert_eq!(region.translate(-2, 0), Region::from(rect(0, 0, 4, 4)));
+
+        // Rects past the coordinate space are cut off.
+        assert_eq!(
+            Region::from(rect(u32::MAX - 1, 0, 4, 1)).rects(),
+            [rect(u32::MAX - 1, 0, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn history_repairs_older_buffers() {
+        let (width, height) = (NonZeroU32::new(8).unwrap(), NonZeroU32::new(8).unwrap());
+        let mut history = DamageHistory::new(2);
+        for i in 0..3 {
+            history.record(rect(i, i, 1, 1).into());
+        }
+
+        assert!(history.repair_region(1, width, height).is_empty());
+        assert_eq!(
+            history.repair_region(2, width, height).rects(),
+            [rect(2, 2, 1, 1)]
+        );
+        assert_eq!(
+            history.repair_region(3, width, height).rects(),
+            [rect(1, 1, 1, 1), rect(2, 2, 1, 1)]
+        );
+        assert_eq!(
+            history.repair_region(4, width, height).rects(),
+            [rect(0, 0, 8, 8)]
+        );
+        assert_eq!(
+            history.repair_region(0, width, height).rects(),
+            [rect(0, 0, 8, 8)]
+        );
+    }
+
+    #[test]
+    fn damage_is_checked_against_the_size() {
+        let (width, height) = (NonZeroU32::new(4).unwrap(), NonZeroU32::new(4).unwrap());
+        let inside = [rect(0, 0, 4, 4)];
+        let outside = [rect(2, 3, 4, 2), rect(4, 0, 1, 1), rect(u32::MAX, 0, 2, 1)];
+
+        for policy in [DamagePolicy::Strict, DamagePolicy::Clip] {
+            let checked = check_damage(&inside, width, height, policy).unwrap();
+            assert_eq!(*checked, inside);
+        }
+        assert_eq!(
+            *check_damage(&outside, width, height, DamagePolicy::Clip).unwrap(),
+            [rect(2, 3, 2, 1)]
+        );
+        assert!(matches!(
+            check_damage(&outside, width, height, DamagePolicy::Strict),
+            Err(SoftBufferError::DamageOutOfRange { rect }) if rect == outside[0]
+        ));
+    }
+
+    #[test]
+    fn slivers_are_merged() {
+        // A rough circle, one row at a time.
+        let damage = [
+            rect(3, 0, 4, 1),
+            rect(2, 1, 6, 1),
+            rect(1, 2, 8, 1),
+            rect(1, 3, 8, 1),
+            rect(2, 4, 6, 1),
+            rect(3, 5, 4, 1),
+        ];
+        assert_eq!(*simplify_damage(&damage), [rect(1, 0, 8, 6)]);
+
+        // Far apart rects stay apart.
+        let damage = [rect(0, 0, 1, 1), rect(10, 10, 1, 1)];
+        assert_eq!(*simplify_damage(&damage), damage);
+
+        let many = (0..20)
+            .map(|i| rect(i * 4, i * 4, 1, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(*simplify_damage(&many), [rect(0, 0, 77, 77)]);
+    }
+
+    #[test]
+    fn history_tracks_the_older_buffer() {
+        let mut surface = test_surface(4, 4);
+        surface.set_damage_history(1);
+
+        let buffer = surface.buffer_mut().unwrap();
+        assert_eq!(buffer.repair_region().unwrap().area(), 16);
+        buffer.present().unwrap();
+
+        let damage = rect(1, 1, 2, 1);
+        let buffer = surface.buffer_mut().unwrap();
+        buffer.present_with_damage(&[damage]).unwrap();
+
+        // The third buffer is the first one again, which missed the second frame.
+        let buffer = surface.buffer_mut().unwrap();
+        assert_eq!(buffer.age(), 2);
+        assert_eq!(buffer.repair_region().unwrap().rects(), [damage]);
+    }
+}
@@ -0,0 +1,282 @@
+//! Rectangular views into a [`Buffer`](crate::Buffer).
+
+use crate::Rect;
+use std::marker::PhantomData;
+use std::num::NonZeroU32;
+use std::ptr::NonNull;
+use std::slice;
+
+/// A mutable view of a rectangle of a [`Buffer`](crate::Buffer).
+///
+/// Returned by [`Buffer::region_mut`](crate::Buffer::region_mut). The pixels are in the same
+/// format as the buffer, but rows are only [`SubBuffer::width`] pixels long. Positions passed to
+/// the methods of this type are relative to the top left corner of the view.
+///
+/// A view can be split into disjoint views with [`SubBuffer::split_at_row`] and
+/// [`SubBuffer::split_at_column`]. Views are [`Send`], so the parts can be drawn to on different
+/// threads at the same time.
+pub struct SubBuffer<'a> {
+    /// The top left pixel of the view.
+    ptr: NonNull<u32>,
+
+    /// The number of pixels from the start of one row to the next.
+    stride: usize,
+
+    /// The area of the buffer this views.
+    rect: Rect,
+
+    _marker: PhantomData<&'a mut [u32]>,
+}
+
+// SAFETY: A `SubBuffer` is a unique borrow of its pixels, just like a `&mut [u32]`.
+unsafe impl Send for SubBuffer<'_> {}
+unsafe impl Sync for SubBuffer<'_> {}
+
+impl<'a> SubBuffer<'a> {
+    /// Create a view of `rect` in `pixels`, which has rows that are `stride` pixels apart.
+    ///
+    /// Returns `None` if `rect` doesn't fit.
+    pub(crate) fn new(pixels: &'a mut [u32], stride: usize, rect: Rect) -> Option<Self> {
+        let right = (rect.x as usize).checked_add(rect.width.get() as usize)?;
+        let last_row = (rect.y as usize)
+            .checked_add(rect.height.get() as usize - 1)?
+            .checked_mul(stride)?;
+        if right > stride || last_row.checked_add(right)? > pixels.len() {
+            return None;
+        }
+
+        let offset = rect.y as usize * stride + rect.x as usize;
+        // SAFETY: `offset` is within `pixels`, as checked above.
+        let ptr = unsafe { NonNull::new_unchecked(pixels.as_mut_ptr().add(offset)) };
+        Some(Self {
+            ptr,
+            stride,
+            rect,
+            _marker: PhantomData,
+        })
+    }
+
+    /// The area of the buffer this view covers, in the coordinates of the buffer.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// The width of the view.
+    pub fn width(&self) -> NonZeroU32 {
+        self.rect.width
+    }
+
+    /// The height of the view.
+    pub fn height(&self) -> NonZeroU32 {
+        self.rect.height
+    }
+
+    /// Get row `y` of the view.
+    ///
+    /// Returns `None` if `y` is outside of the view.
+    pub fn row(&self, y: u32) -> Option<&[u32]> {
+        if y >= self.rect.height.get() {
+            return None;
+        }
+
+        // SAFETY: The row is within the view, which `new` checked is within the buffer.
+        Some(unsafe { slice::from_raw_parts(self.row_ptr(y), self.rect.width.get() as usize) })
+    }
+
+    /// Get row `y` of the view mutably.
+    ///
+    /// Returns `None` if `y` is outside of the view.
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [u32]> {
+        if y >= self.rect.height.get() {
+            return None;
+        }
+
+        // SAFETY: The row is within the view, which `new` checked is within the buffer, and we
+        // have unique access to it.
+        Some(unsafe { slice::from_raw_parts_mut(self.row_ptr(y), self.rect.width.get() as usize) })
+    }
+
+    /// Iterate mutably over the rows of the view from top to bottom.
+    pub fn rows_mut(&mut self) -> impl ExactSizeIterator<Item = &mut [u32]> + '_ {
+        let (ptr, stride, width) = (self.ptr, self.stride, self.rect.width.get() as usize);
+        (0..self.rect.height.get() as usize).map(move |y| {
+            // SAFETY: Every row is within the view, the rows don't overlap, and the iterator
+            // borrows the view mutably.
+            unsafe { slice::from_raw_parts_mut(ptr.as_ptr().add(y * stride), width) }
+        })
+    }
+
+    /// Get the pixel at `(x, y)`.
+    ///
+    /// Returns `None` if the position is outside of the view.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<&u32> {
+        self.row(y)?.get(x as usize)
+    }
+
+    /// Get the pixel at `(x, y)` mutably.
+    ///
+    /// Returns `None` if the position is outside of the view.
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> Option<&mut u32> {
+        self.row_mut(y)?.get_mut(x as usize)
+    }
+
+    /// Fill the view with `pixel`.
+    pub fn fill(&mut self, pixel: u32) {
+        for row in self.rows_mut() {
+            row.fill(pixel);
+        }
+    }
+
+    /// Split the view into the rows above `y` and the rows from `y` on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either part would be empty, that is if `y` is `0` or not less than the height.
+    pub fn split_at_row(self, y: u32) -> (Self, Self) {
+        assert!(
+            y > 0 && y < self.rect.height.get(),
+            "row {y} doesn't split a view of height {}",
+            self.rect.height
+        );
+
+        let top = Rect {
+            height: NonZeroU32::new(y).unwrap(),
+            ..self.rect
+        };
+        let bottom = Rect {
+            y: self.rect.y + y,
+            height: NonZeroU32::new(self.rect.height.get() - y).unwrap(),
+            ..self.rect
+        };
+
+        // SAFETY: Row `y` is within the view.
+        let bottom_ptr = unsafe { NonNull::new_unchecked(self.row_ptr(y)) };
+        (self.with(self.ptr, top), self.with(bottom_ptr, bottom))
+    }
+
+    /// Split the view into the columns left of `x` and the columns from `x` on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either part would be empty, that is if `x` is `0` or not less than the width.
+    pub fn split_at_column(self, x: u32) -> (Self, Self) {
+        assert!(
+            x > 0 && x < self.rect.width.get(),
+            "column {x} doesn't split a view of width {}",
+            self.rect.width
+        );
+
+        let left = Rect {
+            width: NonZeroU32::new(x).unwrap(),
+            ..self.rect
+        };
+        let right = Rect {
+            x: self.rect.x + x,
+            width: NonZeroU32::new(self.rect.width.get() - x).unwrap(),
+            ..self.rect
+        };
+
+        // SAFETY: Column `x` is within the view.
+        let right_ptr = unsafe { NonNull::new_unchecked(self.ptr.as_ptr().add(x as usize)) };
+        (self.with(self.ptr, left), self.with(right_ptr, right))
+    }
+
+    /// A pointer to the start of row `y`, which must be within the view.
+    fn row_ptr(&self, y: u32) -> *mut u32 {
+        // SAFETY: The caller makes sure the row is within the view, so within the buffer.
+        unsafe { self.ptr.as_ptr().add(y as usize * self.stride) }
+    }
+
+    /// A view of part of this one, starting at `ptr`.
+    fn with(&self, ptr: NonNull<u32>, rect: Rect) -> Self {
+        Self {
+            ptr,
+            stride: self.stride,
+            rect,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_surface;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        }
+    }
+
+    #[test]
+    fn views_respect_stride() {
+        // A 3x3 buffer with rows padded to 4 pixels.
+        let mut pixels = [0; 12];
+        let mut view = SubBuffer::new(&mut pixels, 4, rect(1, 1, 2, 2)).unwrap();
+        view.fill(1);
+        *view.pixel_mut(1, 1).unwrap() = 2;
+        assert_eq!(view.pixel(2, 0), None);
+
+        assert_eq!(pixels, [0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 2, 0]);
+        assert!(SubBuffer::new(&mut pixels, 4, rect(3, 0, 2, 1)).is_none());
+        assert!(SubBuffer::new(&mut pixels, 4, rect(0, 2, 1, 2)).is_none());
+    }
+
+    #[test]
+    fn split_across_threads() {
+        let mut pixels = [0; 16];
+        let view = SubBuffer::new(&mut pixels, 4, rect(0, 0, 4, 4)).unwrap();
+        let (top, bottom) = view.split_at_row(1);
+        let (left, right) = bottom.split_at_column(3);
+        assert_eq!(right.rect(), rect(3, 1, 1, 3));
+
+        std::thread::scope(|scope| {
+            for (i, mut part) in [top, left, right].into_iter().enumerate() {
+                scope.spawn(move || part.fill(i as u32 + 1));
+            }
+        });
+
+        assert_eq!(pixels, [1, 1, 1, 1, 2, 2, 2, 3, 2, 2, 2, 3, 2, 2, 2, 3]);
+    }
+
+    #[test]
+    fn regions_are_clipped_to_the_size() {
+        let mut surface = test_surface(3, 2);
+        let mut buffer = surface.buffer_mut().unwrap();
+
+        buffer.region_mut(rect(1, 0, 2, 2)).unwrap().fill(9);
+        assert_eq!(*buffer, [0, 9, 9, 0, 9, 9]);
+        assert!(buffer.region_mut(rect(1, 0, 3, 2)).is_none());
+    }
+
+    #[test]
+    fn bands_cover_the_buffer() {
+        let mut surface = test_surface(2, 5);
+        let mut buffer = surface.buffer_mut().unwrap();
+
+        let mut damage = Vec::new();
+        std::thread::scope(|scope| {
+            for (i, mut band) in buffer.split_rows_mut(3).into_iter().enumerate() {
+                damage.push(band.rect());
+                scope.spawn(move || band.fill(i as u32));
+            }
+        });
+        assert_eq!(*buffer, [0, 0, 0, 0, 1, 1, 1, 1, 2, 2]);
+        assert_eq!(
+            damage
+                .iter()
+                .map(|rect| (rect.y, rect.height.get()))
+                .collect::<Vec<_>>(),
+            [(0, 2), (2, 2), (4, 1)]
+        );
+        buffer.present_with_damage(&damage).unwrap();
+
+        // More bands than rows gives one band per row.
+        let mut buffer = surface.buffer_mut().unwrap();
+        assert_eq!(buffer.split_rows_mut(8).len(), 5);
+    }
+}
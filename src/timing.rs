@@ -0,0 +1,17 @@
+//! When presented frames are shown.
+
+use std::time::Duration;
+
+/// When a presented frame was shown, as reported by the display server.
+///
+/// Returned by [`Surface::last_frame_timing`](crate::Surface::last_frame_timing).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct FrameTiming {
+    /// When the frame was shown, on the monotonic clock of the display server.
+    pub time: Duration,
+
+    /// The number of vertical blanks of the display before the frame was shown, which is also
+    /// known as the media stream counter.
+    pub msc: u64,
+}
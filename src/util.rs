@@ -2,6 +2,7 @@
 #![allow(dead_code)]
 
 use std::cmp;
+use std::io;
 use std::num::NonZeroU32;
 
 use crate::Rect;
@@ -85,6 +86,62 @@ pub(crate) fn union_damage(damage: &[Rect]) -> Option<Rect> {
     })
 }
 
+/// Copy `rects` from `src` to `dst`, which both have rows that are `stride` pixels apart.
+pub(crate) fn copy_rects(src: &[u32], dst: &mut [u32], stride: usize, rects: &[Rect]) {
+    for rect in rects {
+        let (x, width) = (rect.x as usize, rect.width.get() as usize);
+        for y in rect.y as usize..(rect.y + rect.height.get()) as usize {
+            let row = y * stride + x..y * stride + x + width;
+            dst[row.clone()].copy_from_slice(&src[row]);
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static FAULTS: std::cell::RefCell<Vec<(&'static str, i32)>> = Default::default();
+}
+
+/// Fail with an injected error if a test asked for one at `point`.
+///
+/// This does nothing outside of tests.
+#[inline]
+pub(crate) fn check_fault(point: &'static str) -> io::Result<()> {
+    #[cfg(test)]
+    {
+        let errno = FAULTS.with(|faults| {
+            let mut faults = faults.borrow_mut();
+            let index = faults.iter().position(|&(p, _)| p == point)?;
+            Some(faults.remove(index).1)
+        });
+        if let Some(errno) = errno {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+    }
+
+    let _ = point;
+    Ok(())
+}
+
+/// Make the next `check_fault(point)` on this thread fail with `errno`.
+#[cfg(test)]
+pub(crate) fn inject_fault(point: &'static str, errno: i32) {
+    FAULTS.with(|faults| faults.borrow_mut().push((point, errno)));
+}
+
+/// A headless surface of the given size, for testing the core through it.
+#[cfg(test)]
+pub(crate) fn test_surface(
+    width: u32,
+    height: u32,
+) -> crate::Surface<crate::NoDisplayHandle, crate::NoWindowHandle> {
+    crate::Surface::new_headless(
+        NonZeroU32::new(width).unwrap(),
+        NonZeroU32::new(height).unwrap(),
+    )
+    .unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;